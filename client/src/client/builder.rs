@@ -0,0 +1,212 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{
+    env,
+    net::{SocketAddr, ToSocketAddrs as _},
+    sync::Arc,
+};
+
+use jsonrpc_server_utils::tokio::runtime::{
+    Builder as RawRuntimeBuilder01, Runtime as RawRuntime01,
+};
+use parking_lot::RwLock;
+use tokio::runtime::{Builder as RawRuntimeBuilder, Runtime as RawRuntime};
+use url::Url;
+
+use super::{Client, TcpOptions};
+use crate::error::{Error, Result};
+
+const ENV_RPC_URL: &str = "CKB_RPC_URL";
+const ENV_TCP_ADDR: &str = "CKB_TCP_ADDR";
+
+/// Builds a `Client` with all its transports at once.
+///
+/// If no runtimes are provided, the builder creates them.
+#[derive(Default)]
+pub struct ClientBuilder {
+    runtimes: Option<(Arc<RawRuntime>, Arc<RwLock<RawRuntime01>>)>,
+    worker_threads: Option<usize>,
+    http: Option<Url>,
+    tcp: Option<SocketAddr>,
+    tcp_options: TcpOptions,
+    user_agent: Option<String>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn runtime(mut self, rt: Arc<RawRuntime>, rt01: Arc<RwLock<RawRuntime01>>) -> Self {
+        self.runtimes = Some((rt, rt01));
+        self
+    }
+
+    /// Sets the count of the worker threads of each runtime which is created by the builder.
+    ///
+    /// The subscriptions and the requests run on the worker threads. With 1 worker thread,
+    /// they run one by one, similar to a current-thread runtime. The default is the count of
+    /// CPU cores. It's ignored if the runtimes are provided.
+    pub fn worker_threads(mut self, count: usize) -> Self {
+        self.worker_threads = Some(count);
+        self
+    }
+
+    pub fn http(mut self, url: Url) -> Self {
+        self.http = Some(url);
+        self
+    }
+
+    /// Sets a transport by a URL, the scheme decides the transport:
+    /// - "http": HTTP.
+    /// - "tcp": TCP, such as "tcp://127.0.0.1:18114".
+    ///
+    /// TLS and WebSocket are not supported, so "https", "ws" and "wss" are rejected.
+    pub fn url_str(self, input: &str) -> Result<Self> {
+        let url = Url::parse(input)
+            .map_err(|err| Error::parse(format!("invalid url {:?}: {}", input, err)))?;
+        match url.scheme() {
+            "http" => Ok(self.http(url)),
+            "tcp" => {
+                let addr = url
+                    .socket_addrs(|| None)
+                    .map_err(|err| {
+                        Error::parse(format!("invalid tcp address {:?}: {}", input, err))
+                    })?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| Error::parse(format!("unresolved tcp address {:?}", input)))?;
+                Ok(self.tcp(addr))
+            }
+            "https" => Err(Error::invalid_argument(format!(
+                "TLS is not supported, but got {:?}",
+                input
+            ))),
+            "ws" | "wss" => Err(Error::invalid_argument(format!(
+                "websocket is not supported, but got {:?}",
+                input
+            ))),
+            scheme => Err(Error::invalid_argument(format!(
+                "url scheme should be \"http\" or \"tcp\", but got {:?}",
+                scheme
+            ))),
+        }
+    }
+
+    /// Sets the transports by the environment variables:
+    /// - `CKB_RPC_URL`: required, a URL for `url_str`.
+    /// - `CKB_TCP_ADDR`: optional, the address of TCP, such as "127.0.0.1:18114".
+    pub fn env(self) -> Result<Self> {
+        let url = env::var(ENV_RPC_URL).map_err(|err| {
+            Error::invalid_argument(format!(
+                "environment variable {} is required: {}",
+                ENV_RPC_URL, err
+            ))
+        })?;
+        let mut builder = self.url_str(&url)?;
+        match env::var(ENV_TCP_ADDR) {
+            Ok(input) => {
+                let addr = input
+                    .to_socket_addrs()
+                    .map_err(|err| {
+                        Error::parse(format!("invalid tcp address {:?}: {}", input, err))
+                    })?
+                    .next()
+                    .ok_or_else(|| Error::parse(format!("unresolved tcp address {:?}", input)))?;
+                builder = builder.tcp(addr);
+            }
+            Err(env::VarError::NotPresent) => {}
+            Err(err) => {
+                return Err(Error::invalid_argument(format!(
+                    "environment variable {} is invalid: {}",
+                    ENV_TCP_ADDR, err
+                )))
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Sets the `User-Agent` header of the HTTP requests, see `Client::set_user_agent`.
+    pub fn user_agent(mut self, user_agent: String) -> Self {
+        self.user_agent = Some(user_agent);
+        self
+    }
+
+    pub fn tcp(mut self, addr: SocketAddr) -> Self {
+        self.tcp = Some(addr);
+        self
+    }
+
+    /// Sets the options of the TCP socket, see `Client::enable_tcp_with_options`.
+    pub fn tcp_options(mut self, options: TcpOptions) -> Self {
+        self.tcp_options = options;
+        self
+    }
+
+    pub fn build(self) -> Result<Client> {
+        if self.http.is_none() && self.tcp.is_none() {
+            return Err(Error::invalid_argument(
+                "at least one of http and tcp should be enabled",
+            ));
+        }
+        let (rt, rt01) = if let Some(runtimes) = self.runtimes {
+            runtimes
+        } else {
+            log::trace!("create runtimes for the client");
+            let mut builder = RawRuntimeBuilder::new_multi_thread();
+            let mut builder01 = RawRuntimeBuilder01::new();
+            if let Some(count) = self.worker_threads {
+                if count == 0 {
+                    return Err(Error::invalid_argument(
+                        "the count of worker threads should be greater than 0",
+                    ));
+                }
+                builder.worker_threads(count);
+                builder01.core_threads(count);
+            }
+            let rt = builder.enable_all().build().map_err(Error::runtime)?;
+            let rt01 = builder01.build().map_err(Error::runtime)?;
+            (Arc::new(rt), Arc::new(RwLock::new(rt01)))
+        };
+        let mut client = Client::new(rt, rt01);
+        if let Some(user_agent) = self.user_agent {
+            client.set_user_agent(user_agent);
+        }
+        if let Some(ref url) = self.http {
+            client.enable_http(url)?;
+        }
+        if let Some(ref addr) = self.tcp {
+            client.enable_tcp_with_options(addr, self.tcp_options)?;
+        }
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_str_rejects_unsupported_schemes() {
+        for input in &[
+            "https://127.0.0.1:8114",
+            "ws://127.0.0.1:18114",
+            "wss://127.0.0.1:18114",
+            "ftp://127.0.0.1:8114",
+        ] {
+            match Client::builder().url_str(input) {
+                Err(Error::InvalidArgument(_)) => {}
+                Err(err) => panic!("unexpected error for {:?}: {}", input, err),
+                Ok(_) => panic!("{:?} should be rejected", input),
+            }
+        }
+        assert!(Client::builder().url_str("http://127.0.0.1:8114").is_ok());
+        assert!(Client::builder().url_str("tcp://127.0.0.1:18114").is_ok());
+    }
+}
@@ -0,0 +1,2232 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use futures::{channel::mpsc, compat::Future01CompatExt as _, Stream};
+use jsonrpc_core::{
+    self as jsonrpc,
+    futures::future,
+    serde::{de::DeserializeOwned, Serialize},
+};
+use tentacle_multiaddr::{Multiaddr, Protocol};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use uckb_jsonrpc_core::types::{core, fixed, packed, prelude::*, rpc};
+
+use super::HttpClient;
+use crate::{
+    error::{Error, Result},
+    parse, system, Client,
+};
+
+// The first node version which accepts the `outputs_validator` argument of `send_transaction`.
+const OUTPUTS_VALIDATOR_MIN_VERSION: (u64, u64, u64) = (0, 36, 0);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerSyncState {
+    pub node_id: String,
+    pub is_outbound: bool,
+    pub best_known_header_number: Option<core::BlockNumber>,
+    pub last_common_header_number: Option<core::BlockNumber>,
+    pub inflight_count: u64,
+    pub can_fetch_count: u64,
+    pub local_best_known_block_number: core::BlockNumber,
+}
+
+/// The aggregation of the connected peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerSummary {
+    pub inbound_count: usize,
+    pub outbound_count: usize,
+    /// The count of peers for each node version.
+    pub versions: HashMap<String, usize>,
+    /// `None` if there are no peers.
+    pub average_connected_duration: Option<Duration>,
+    /// The average of the last ping durations, `None` if no peers are pinged.
+    pub average_ping_duration: Option<Duration>,
+}
+
+/// The differences between a fork block and the main chain block at the same number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkDiff {
+    pub block_number: core::BlockNumber,
+    pub fork_block_hash: fixed::H256,
+    /// `None` if the main chain is shorter than the fork.
+    pub main_block_hash: Option<fixed::H256>,
+    /// Transactions in the fork block but not in the main chain block.
+    pub added: Vec<fixed::H256>,
+    /// Transactions in the main chain block but not in the fork block.
+    pub removed: Vec<fixed::H256>,
+}
+
+/// The DAO data at the start of an epoch.
+///
+/// The DAO field of a header is 32 bytes, which are 4 little-endian `u64` values in order:
+/// - `C`: the total issuance.
+/// - `AR`: the accumulated rate, which starts from `10^16` at the genesis block.
+/// - `S`: the total unissued secondary issuance.
+/// - `U`: the total occupied capacities.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochDaoInfo {
+    pub epoch: rpc::EpochView,
+    pub start_block_hash: fixed::H256,
+    pub dao: packed::Byte32,
+    pub total_issuance: core::Capacity,
+    pub accumulated_rate: u64,
+    pub secondary_issuance: core::Capacity,
+    pub occupied_capacities: core::Capacity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkType {
+    Mainnet,
+    Testnet,
+    Dev,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeStatus {
+    pub chain: String,
+    pub tip_block_number: core::BlockNumber,
+    pub best_known_block_number: core::BlockNumber,
+    pub is_initial_block_download: bool,
+    pub peers_count: usize,
+    pub pending_txs_count: u64,
+    pub proposed_txs_count: u64,
+    pub orphan_txs_count: u64,
+}
+
+/// A block is identified by either its hash or its number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BlockId {
+    Hash(fixed::H256),
+    Number(core::BlockNumber),
+}
+
+/// The status of an out point in the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutPointStatus {
+    Live(packed::CellOutput),
+    /// The cell is spent.
+    Dead,
+    /// The transaction which creates the cell is not in the chain yet, or the cell is spent
+    /// and the node doesn't keep it.
+    Unknown,
+}
+
+/// A witness of a transaction, which is decoded as `WitnessArgs` if possible.
+#[derive(Debug, Clone)]
+pub enum Witness {
+    Args(packed::WitnessArgs),
+    /// The witness isn't a `WitnessArgs`, such as an empty witness or a custom format.
+    Raw(packed::Bytes),
+}
+
+/// The well-known system scripts of a chain, which are extracted from the genesis block.
+///
+/// The code hashes are for the hash type `type`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenesisInfo {
+    pub hash: fixed::H256,
+    pub secp256k1_code_hash: fixed::H256,
+    pub secp256k1_dep: packed::CellDep,
+    pub multisig_code_hash: fixed::H256,
+    pub multisig_dep: packed::CellDep,
+    pub dao_code_hash: fixed::H256,
+    pub dao_dep: packed::CellDep,
+}
+
+impl Witness {
+    pub fn decode(data: &[u8]) -> Self {
+        packed::WitnessArgs::from_slice(data)
+            .map(Self::Args)
+            .unwrap_or_else(|_| Self::Raw(data.pack()))
+    }
+}
+
+impl EpochDaoInfo {
+    fn new(epoch: rpc::EpochView, header: &core::HeaderView) -> Self {
+        let dao = header.dao();
+        let data = dao.raw_data();
+        let read_u64 = |index: usize| {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&data[index * 8..(index + 1) * 8]);
+            u64::from_le_bytes(buf)
+        };
+        Self {
+            epoch,
+            start_block_hash: header.hash().unpack(),
+            total_issuance: core::Capacity::shannons(read_u64(0)),
+            accumulated_rate: read_u64(1),
+            secondary_issuance: core::Capacity::shannons(read_u64(2)),
+            occupied_capacities: core::Capacity::shannons(read_u64(3)),
+            dao,
+        }
+    }
+}
+
+impl GenesisInfo {
+    pub fn new(genesis: &core::BlockView) -> Result<Self> {
+        let (secp256k1_code_hash, secp256k1_dep) = system::secp256k1_code_hash_and_dep(genesis)?;
+        let (multisig_code_hash, multisig_dep) = system::multisig_code_hash_and_dep(genesis)?;
+        let (dao_code_hash, dao_dep) = system::dao_code_hash_and_dep(genesis)?;
+        Ok(Self {
+            hash: genesis.hash().unpack(),
+            secp256k1_code_hash,
+            secp256k1_dep,
+            multisig_code_hash,
+            multisig_dep,
+            dao_code_hash,
+            dao_dep,
+        })
+    }
+}
+
+impl NetworkType {
+    /// Detects the network by the chain name in `get_blockchain_info`.
+    pub fn from_chain_name(chain: &str) -> Self {
+        match chain {
+            "ckb" => Self::Mainnet,
+            "ckb_testnet" => Self::Testnet,
+            _ => Self::Dev,
+        }
+    }
+}
+
+impl From<fixed::H256> for BlockId {
+    fn from(hash: fixed::H256) -> Self {
+        Self::Hash(hash)
+    }
+}
+
+impl From<core::BlockNumber> for BlockId {
+    fn from(number: core::BlockNumber) -> Self {
+        Self::Number(number)
+    }
+}
+
+// A hex string starts with "0x" is a hash, otherwise it should be a decimal number.
+impl FromStr for BlockId {
+    type Err = Error;
+    fn from_str(input: &str) -> Result<Self> {
+        if input.starts_with("0x") {
+            parse::parse_h256(input).map(Self::Hash)
+        } else {
+            input
+                .parse()
+                .map(Self::Number)
+                .map_err(|err| Error::parse(format!("invalid block number {:?}: {}", input, err)))
+        }
+    }
+}
+
+impl Client {
+    //
+    // Module Chain
+    //
+
+    /// Compares a fork block with the main chain block at the same number.
+    ///
+    /// Returns `None` if the hash is not a known fork block.
+    pub fn diff_fork_block(&self, fork_hash: fixed::H256) -> Result<Option<ForkDiff>> {
+        let fork_block = if let Some(block) = self.get_fork_block(fork_hash.clone(), None)? {
+            block
+        } else {
+            return Ok(None);
+        };
+        let block_number = fork_block.header.inner.number.into();
+        let fork_txs = fork_block
+            .transactions
+            .into_iter()
+            .map(|tx| tx.hash)
+            .collect::<Vec<_>>();
+        let (main_block_hash, main_txs) =
+            if let Some(block) = self.get_block_by_number(block_number, None)? {
+                let txs = block
+                    .tx_hashes()
+                    .iter()
+                    .map(|hash| hash.unpack())
+                    .collect::<Vec<fixed::H256>>();
+                (Some(block.hash().unpack()), txs)
+            } else {
+                (None, Vec::new())
+            };
+        let diff = {
+            let fork_set = fork_txs.iter().collect::<HashSet<_>>();
+            let main_set = main_txs.iter().collect::<HashSet<_>>();
+            let added = fork_txs
+                .iter()
+                .filter(|hash| !main_set.contains(hash))
+                .cloned()
+                .collect();
+            let removed = main_txs
+                .iter()
+                .filter(|hash| !fork_set.contains(hash))
+                .cloned()
+                .collect();
+            ForkDiff {
+                block_number,
+                fork_block_hash: fork_hash,
+                main_block_hash,
+                added,
+                removed,
+            }
+        };
+        Ok(Some(diff))
+    }
+
+    /// Reads the DAO data from the header of the first block in an epoch.
+    ///
+    /// Returns `None` if the epoch hasn't started.
+    pub fn epoch_dao_info(&self, epoch_number: core::EpochNumber) -> Result<Option<EpochDaoInfo>> {
+        let epoch = if let Some(epoch) = self.get_epoch_by_number(epoch_number)? {
+            epoch
+        } else {
+            return Ok(None);
+        };
+        let start_number = epoch.start_number.into();
+        let info = self
+            .get_header_by_number(start_number, None)?
+            .map(|header| EpochDaoInfo::new(epoch, &header));
+        Ok(info)
+    }
+
+    /// Waits until the tip of the node reaches the target, then returns the header at the
+    /// target number.
+    pub fn wait_for_block_number(
+        &self,
+        target: core::BlockNumber,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<core::HeaderView> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let tip_number = self.get_tip_block_number()?;
+            if tip_number >= target {
+                if let Some(header) = self.get_header_by_number(target, None)? {
+                    return Ok(header);
+                }
+            }
+            if Instant::now() + poll_interval > deadline {
+                return Err(Error::timeout(format!(
+                    "block#{} is not reached in {:?}, the tip is block#{}",
+                    target, timeout, tip_number
+                )));
+            }
+            log::trace!("wait for block#{}, the tip is block#{}", target, tip_number);
+            thread::sleep(poll_interval);
+        }
+    }
+
+    /// Gets the proof of transactions, and the number of the block which the proof refers to.
+    pub fn get_transaction_proof_with_number(
+        &self,
+        tx_hashes: Vec<fixed::H256>,
+        block_hash: Option<fixed::H256>,
+    ) -> Result<(rpc::TransactionProof, core::BlockNumber)> {
+        let proof = self.get_transaction_proof(tx_hashes, block_hash)?;
+        let header = self
+            .get_header(proof.block_hash.clone(), None)?
+            .ok_or_else(|| {
+                Error::rpc_other(format!(
+                    "the header of block {:#x} in the proof is not found",
+                    proof.block_hash
+                ))
+            })?;
+        Ok((proof, header.number()))
+    }
+
+    /// Checks that a transaction is included in the block of a trusted header.
+    ///
+    /// The proof from the node is verified locally, the node is only trusted to provide the
+    /// proof. Returns `false` if the proof is for another block or it doesn't lead to the
+    /// transactions root in the trusted header.
+    pub fn verify_transaction_inclusion(
+        &self,
+        tx_hash: fixed::H256,
+        trusted_header: &core::HeaderView,
+    ) -> Result<bool> {
+        let tx_hashes = vec![tx_hash];
+        let proof = self.get_transaction_proof(tx_hashes.clone(), None)?;
+        Ok(system::verify_transaction_proof(
+            &proof,
+            &tx_hashes,
+            trusted_header,
+        ))
+    }
+
+    /// Filters out the out points which are not live any more.
+    ///
+    /// The `get_live_cell` requests are sent concurrently.
+    pub fn get_live_cells_checked(
+        &self,
+        out_points: &[packed::OutPoint],
+    ) -> Result<Vec<packed::OutPoint>> {
+        let http = self.http()?;
+        let futs = out_points
+            .iter()
+            .map(|out_point| http.get_live_cell(out_point.clone(), false))
+            .collect::<Vec<_>>();
+        let cells = self.runtime().block_on_01(future::join_all(futs))?;
+        let live = out_points
+            .iter()
+            .zip(cells)
+            .filter(|(_, cell)| cell.status == "live")
+            .map(|(out_point, _)| out_point.clone())
+            .collect();
+        Ok(live)
+    }
+
+    /// Gets the hashes of many blocks in the main chain, `None` for the numbers beyond the tip.
+    ///
+    /// The `get_block_hash` calls are sent in one JSON-RPC batch, and the results are in the
+    /// same order as the numbers.
+    pub fn get_block_hashes(
+        &self,
+        block_numbers: &[core::BlockNumber],
+    ) -> Result<Vec<Option<fixed::H256>>> {
+        let calls = block_numbers
+            .iter()
+            .map(|block_number| {
+                batch_call("get_block_hash", (rpc::BlockNumber::from(*block_number),))
+            })
+            .collect();
+        self.batch(calls)?.into_iter().map(parse_result).collect()
+    }
+
+    pub fn get_block_any(
+        &self,
+        block_id: BlockId,
+        verbosity: Option<u32>,
+    ) -> Result<Option<core::BlockView>> {
+        match block_id {
+            BlockId::Hash(hash) => self.get_block(hash, verbosity),
+            BlockId::Number(number) => self.get_block_by_number(number, verbosity),
+        }
+    }
+
+    pub fn get_header_any(
+        &self,
+        block_id: BlockId,
+        verbosity: Option<u32>,
+    ) -> Result<Option<core::HeaderView>> {
+        match block_id {
+            BlockId::Hash(hash) => self.get_header(hash, verbosity),
+            BlockId::Number(number) => self.get_header_by_number(number, verbosity),
+        }
+    }
+
+    /// Gets the transactions of a block, the uncles and the proposals are dropped.
+    pub fn get_block_transactions(
+        &self,
+        block_hash: fixed::H256,
+    ) -> Result<Option<Vec<core::TransactionView>>> {
+        self.get_block(block_hash, None)
+            .map(|block_opt| block_opt.map(|block| block.transactions()))
+    }
+
+    pub fn get_block_transactions_by_number(
+        &self,
+        block_number: core::BlockNumber,
+    ) -> Result<Option<Vec<core::TransactionView>>> {
+        self.get_block_by_number(block_number, None)
+            .map(|block_opt| block_opt.map(|block| block.transactions()))
+    }
+
+    /// Gets the system scripts from the genesis block, the result is cached.
+    pub fn genesis_info(&self) -> Result<GenesisInfo> {
+        if let Some(ref genesis_info) = *self.genesis_info.read() {
+            return Ok(genesis_info.clone());
+        }
+        let genesis = self
+            .get_block_by_number(0, None)?
+            .ok_or_else(|| Error::rpc_other("the genesis block is not found"))?;
+        let genesis_info = GenesisInfo::new(&genesis)?;
+        log::debug!("genesis info is {:?}", genesis_info);
+        *self.genesis_info.write() = Some(genesis_info.clone());
+        Ok(genesis_info)
+    }
+
+    /// Gets a block and verifies it against its hash, for the nodes which are not trusted.
+    pub fn get_block_verified(&self, block_hash: fixed::H256) -> Result<Option<core::BlockView>> {
+        let block = if let Some(block) = self.get_block_packed(block_hash.clone())? {
+            // Keep the header as it is, `into_view` resets the merkle roots in the header.
+            block.into_view_without_reset_header()
+        } else {
+            return Ok(None);
+        };
+        let actual: fixed::H256 = block.hash().unpack();
+        if actual != block_hash {
+            return Err(Error::corruption(format!(
+                "request block {:#x}, but the header of the returned block is hashed to {:#x}",
+                block_hash, actual
+            )));
+        }
+        system::verify_block(&block)?;
+        Ok(Some(block))
+    }
+
+    /// Watches the status of a transaction, returns a stream which polls the node and yields
+    /// the status only when it changes.
+    ///
+    /// The node is polled by a task in the runtime of the client, so the stream doesn't block.
+    /// Nothing is yielded while the node doesn't know the transaction. The stream ends after
+    /// the transaction is committed, or after an error. If the transaction is dropped (known
+    /// then unknown, the node doesn't report the rejected transactions), an error is the last
+    /// item. The polling stops when the stream is dropped.
+    pub fn watch_transaction(
+        &self,
+        tx_hash: fixed::H256,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = Result<rpc::TxStatus>> {
+        let (sender, receiver) = mpsc::unbounded();
+        match self.http() {
+            Ok(http) => {
+                let fut = poll_transaction_status(http.clone(), tx_hash, poll_interval, sender);
+                self.runtime().spawn(fut);
+            }
+            Err(err) => {
+                let _ = sender.unbounded_send(Err(err));
+            }
+        }
+        receiver
+    }
+
+    /// Gets the witnesses of a transaction, each witness is decoded as `WitnessArgs`.
+    ///
+    /// A witness which isn't a `WitnessArgs` is returned as `Witness::Raw` rather than an error.
+    /// Returns `None` if the transaction is unknown.
+    pub fn get_transaction_witnesses(&self, tx_hash: fixed::H256) -> Result<Option<Vec<Witness>>> {
+        let witnesses = self.get_transaction(tx_hash)?.map(|tx_with_status| {
+            tx_with_status
+                .transaction
+                .inner
+                .witnesses
+                .iter()
+                .map(|witness| Witness::decode(witness.as_bytes()))
+                .collect()
+        });
+        Ok(witnesses)
+    }
+
+    /// Finds the number and the hash of the block which commits a transaction.
+    ///
+    /// The block hash is from `tx_status.block_hash` of `get_transaction`, or from the
+    /// transaction proof if the node doesn't report it. Returns `None` if the transaction is
+    /// not committed.
+    pub fn transaction_block(
+        &self,
+        tx_hash: fixed::H256,
+    ) -> Result<Option<(core::BlockNumber, fixed::H256)>> {
+        let tx_status = match self.get_transaction(tx_hash.clone())? {
+            Some(tx) if tx.tx_status.status == rpc::Status::Committed => tx.tx_status,
+            _ => return Ok(None),
+        };
+        let block_hash = if let Some(block_hash) = tx_status.block_hash {
+            block_hash
+        } else {
+            log::debug!(
+                "no block hash in the status of {:#x}, use the proof",
+                tx_hash
+            );
+            self.get_transaction_proof(vec![tx_hash], None)?.block_hash
+        };
+        let header = self.get_header(block_hash.clone(), None)?.ok_or_else(|| {
+            Error::rpc_other(format!(
+                "the header of block {:#x} which commits the transaction is not found",
+                block_hash
+            ))
+        })?;
+        Ok(Some((header.number(), block_hash)))
+    }
+
+    /// Checks whether a cell can be spent at the tip, the outputs of a cellbase can't be spent
+    /// until `cellbase_maturity` epochs after the block which commits the cellbase.
+    ///
+    /// The cellbase maturity is read by `get_consensus`. The old nodes don't have it, then
+    /// [`system::DEFAULT_CELLBASE_MATURITY`] is used, which is the maturity of the mainnet and
+    /// the testnet. Returns `true` for the cells which are not created by a cellbase.
+    pub fn is_cellbase_mature(&self, out_point: &packed::OutPoint) -> Result<bool> {
+        let tx_hash: fixed::H256 = out_point.tx_hash().unpack();
+        let tx = self.get_transaction(tx_hash.clone())?.ok_or_else(|| {
+            Error::rpc_invalid_params(format!("transaction {:#x} is not found", tx_hash))
+        })?;
+        let is_cellbase = {
+            let inputs = &tx.transaction.inner.inputs;
+            inputs.len() == 1 && system::is_cellbase_input(&inputs[0].clone().into())
+        };
+        if !is_cellbase {
+            return Ok(true);
+        }
+        let block_hash = match tx.tx_status.block_hash {
+            Some(block_hash) if tx.tx_status.status == rpc::Status::Committed => block_hash,
+            _ => {
+                return Err(Error::rpc_other(format!(
+                    "cellbase {:#x} is not committed",
+                    tx_hash
+                )))
+            }
+        };
+        let header = self.get_header(block_hash.clone(), None)?.ok_or_else(|| {
+            Error::rpc_other(format!(
+                "the header of block {:#x} is not found",
+                block_hash
+            ))
+        })?;
+        // The outputs of the genesis cellbase are always mature.
+        if header.number() == 0 {
+            return Ok(true);
+        }
+        let cellbase_maturity = self.cellbase_maturity()?;
+        let tip_header = self.get_tip_header(None)?;
+        // Same as the maturity verification in the node, but the tip is used as the current
+        // epoch, so the result is conservative.
+        let threshold = cellbase_maturity.to_rational() + header.epoch().to_rational();
+        Ok(tip_header.epoch().to_rational() >= threshold)
+    }
+
+    pub fn out_point_status(&self, out_point: &packed::OutPoint) -> Result<OutPointStatus> {
+        let cell = self.get_live_cell(out_point.clone(), false)?;
+        match cell.status.as_str() {
+            "live" => cell
+                .cell
+                .map(|cell| OutPointStatus::Live(cell.output.into()))
+                .ok_or_else(|| Error::rpc_other("a live cell is returned without the output")),
+            "dead" => Ok(OutPointStatus::Dead),
+            "unknown" => Ok(OutPointStatus::Unknown),
+            status => Err(Error::rpc_other(format!(
+                "unknown cell status {:?}",
+                status
+            ))),
+        }
+    }
+
+    /// Gets the capacities of the inputs of a transaction, in the order of the inputs.
+    ///
+    /// The live cells are read by `get_live_cell`. The spent cells are not returned by it, so
+    /// they are read from the transactions which create them.
+    pub fn resolve_inputs(&self, tx: &packed::Transaction) -> Result<Vec<core::Capacity>> {
+        tx.raw()
+            .inputs()
+            .into_iter()
+            .map(|input| {
+                if system::is_cellbase_input(&input) {
+                    return Err(Error::invalid_argument(
+                        "the input of a cellbase can't be resolved",
+                    ));
+                }
+                let out_point = input.previous_output();
+                let cell = self.get_live_cell(out_point.clone(), false)?;
+                let output: packed::CellOutput = match cell.cell {
+                    Some(info) if cell.status == "live" => info.output.into(),
+                    _ => {
+                        let tx_hash: fixed::H256 = out_point.tx_hash().unpack();
+                        let index: u32 = out_point.index().unpack();
+                        self.get_transaction(tx_hash.clone())?
+                            .and_then(|tx| {
+                                tx.transaction.inner.outputs.into_iter().nth(index as usize)
+                            })
+                            .ok_or_else(|| {
+                                Error::rpc_invalid_params(format!(
+                                    "output {:#x}#{} is not found",
+                                    tx_hash, index
+                                ))
+                            })?
+                            .into()
+                    }
+                };
+                Ok(output.capacity().unpack())
+            })
+            .collect()
+    }
+
+    /// Computes the fee of a transaction, which is the capacities of the inputs minus the
+    /// capacities of the outputs.
+    pub fn transaction_fee(&self, tx: &packed::Transaction) -> Result<core::Capacity> {
+        let sum = |capacities: Vec<core::Capacity>| {
+            capacities
+                .into_iter()
+                .try_fold(core::Capacity::zero(), |sum, capacity| {
+                    sum.safe_add(capacity)
+                })
+                .map_err(|err| Error::invalid_argument(format!("capacity overflow: {}", err)))
+        };
+        let inputs = sum(self.resolve_inputs(tx)?)?;
+        let outputs = sum(tx
+            .raw()
+            .outputs()
+            .into_iter()
+            .map(|output| output.capacity().unpack())
+            .collect())?;
+        inputs.safe_sub(outputs).map_err(|_| {
+            Error::invalid_argument(format!(
+                "the outputs have {} but the inputs have {} only",
+                outputs, inputs
+            ))
+        })
+    }
+
+    /// Waits until a transaction is committed and followed by `confirmations` blocks in the
+    /// main chain, then returns the number and the hash of the block which commits it.
+    ///
+    /// The status is checked again on each poll, so the countdown restarts if the transaction
+    /// is rolled back by a reorg. It fails if the transaction is dropped (known then unknown,
+    /// the node doesn't report the rejected transactions), or after the timeout.
+    pub fn wait_for_transaction(
+        &self,
+        tx_hash: fixed::H256,
+        confirmations: core::BlockNumber,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<(core::BlockNumber, fixed::H256)> {
+        let deadline = Instant::now() + timeout;
+        let mut is_known = false;
+        let mut committed_at: Option<(core::BlockNumber, fixed::H256)> = None;
+        loop {
+            let tx_status = self
+                .get_transaction(tx_hash.clone())?
+                .map(|tx| tx.tx_status);
+            let is_known_now = tx_status.is_some();
+            let committed = match tx_status {
+                Some(rpc::TxStatus {
+                    status: rpc::Status::Committed,
+                    block_hash: Some(block_hash),
+                }) => self
+                    .get_header(block_hash.clone(), None)?
+                    .map(|header| (header.number(), block_hash)),
+                Some(_) => None,
+                None if is_known => {
+                    return Err(Error::rpc_other(format!(
+                        "transaction {:#x} is dropped",
+                        tx_hash
+                    )));
+                }
+                None => None,
+            };
+            is_known |= is_known_now;
+            if committed_at.is_some() && committed_at != committed {
+                log::warn!("transaction {:#x} is rolled back by a reorg", tx_hash);
+            }
+            committed_at = committed;
+            if let Some((block_number, ref block_hash)) = committed_at {
+                let tip_number = self.get_tip_block_number()?;
+                // Make sure the block is still in the main chain.
+                let is_main = self.get_block_hash(block_number)?.as_ref() == Some(block_hash);
+                // never confirmed if the target overflows, such as `u64::MAX` confirmations
+                let is_confirmed = block_number
+                    .checked_add(confirmations)
+                    .is_some_and(|target| tip_number >= target);
+                if is_main && is_confirmed {
+                    return Ok((block_number, block_hash.clone()));
+                }
+            }
+            if Instant::now() + poll_interval > deadline {
+                return Err(Error::timeout(format!(
+                    "transaction {:#x} is not confirmed in {:?}",
+                    tx_hash, timeout
+                )));
+            }
+            log::trace!("wait for transaction {:#x}: {:?}", tx_hash, committed_at);
+            thread::sleep(poll_interval);
+        }
+    }
+
+    /// Gets the epoch relative to the current epoch, for example, `-1` is the previous epoch.
+    ///
+    /// The epoch number is clamped at 0, and `None` is returned for the future epochs.
+    pub fn epoch_offset(&self, offset: i64) -> Result<Option<rpc::EpochView>> {
+        // The epochs after the current epoch don't exist.
+        if offset > 0 {
+            return Ok(None);
+        }
+        let current = self.get_current_epoch()?;
+        if offset == 0 {
+            return Ok(Some(current));
+        }
+        let current_number: core::EpochNumber = current.number.into();
+        let epoch_number = current_number.saturating_sub(offset.unsigned_abs());
+        self.get_epoch_by_number(epoch_number)
+    }
+
+    //
+    // Module Pool
+    //
+
+    /// Sends a transaction with the outputs validator which the connected node supports.
+    ///
+    /// Nodes since v0.36.0 accept `outputs_validator`, for them `Passthrough` is used so
+    /// outputs with any scripts are allowed; for older nodes the argument is omitted.
+    ///
+    /// The node version is read from the cached local node info.
+    pub fn send_transaction_auto(&self, tx: packed::Transaction) -> Result<fixed::H256> {
+        let outputs_validator = if self.node_version()? >= OUTPUTS_VALIDATOR_MIN_VERSION {
+            Some(rpc::OutputsValidator::Passthrough)
+        } else {
+            None
+        };
+        self.send_transaction(tx, outputs_validator)
+    }
+
+    /// Checks the outputs of a transaction locally, the same as the outputs validator of
+    /// `send_transaction`, so the rejection is known before sending.
+    ///
+    /// The system scripts are from `genesis_info`.
+    pub fn validate_outputs_against(
+        &self,
+        tx: &packed::Transaction,
+        validator: &rpc::OutputsValidator,
+    ) -> Result<()> {
+        if let rpc::OutputsValidator::Passthrough = validator {
+            return Ok(());
+        }
+        system::validate_outputs_against(tx, validator, &self.genesis_info()?)
+    }
+
+    /// Checks the structure of a transaction before sending it, so the construction bugs are
+    /// reported locally.
+    pub fn send_transaction_checked(
+        &self,
+        tx: packed::Transaction,
+        outputs_validator: Option<rpc::OutputsValidator>,
+    ) -> Result<fixed::H256> {
+        system::validate_transaction_structure(&tx)?;
+        self.send_transaction(tx, outputs_validator)
+    }
+
+    /// Builds, signs and sends a transaction, and rebuilds it with a higher fee rate when it's
+    /// rejected because of a low fee rate.
+    ///
+    /// The fee rate starts from `initial_fee_rate` and increases evenly in `steps` steps to
+    /// `max_fee_rate`. The steps are at most the difference of the fee rates, so each fee rate
+    /// is sent once. Only the rejections by the minimum fee rate trigger a bump, other errors
+    /// are returned immediately.
+    pub fn send_transaction_with_fee_bump<B, S>(
+        &self,
+        mut tx_builder: B,
+        mut signer: S,
+        initial_fee_rate: core::FeeRate,
+        max_fee_rate: core::FeeRate,
+        steps: u64,
+    ) -> Result<fixed::H256>
+    where
+        B: FnMut(core::FeeRate) -> Result<packed::Transaction>,
+        S: FnMut(packed::Transaction) -> Result<packed::Transaction>,
+    {
+        if initial_fee_rate > max_fee_rate {
+            return Err(Error::invalid_argument(format!(
+                "the initial fee rate {} is higher than the max fee rate {}",
+                initial_fee_rate, max_fee_rate
+            )));
+        }
+        let initial = initial_fee_rate.as_u64();
+        let range = max_fee_rate.as_u64() - initial;
+        let steps = steps.min(range).max(1);
+        let mut last_error = None;
+        let mut last_fee_rate = None;
+        for step in 0..=steps {
+            // The offset is at most the range, but the product may overflow `u64`.
+            let offset = u128::from(range) * u128::from(step) / u128::from(steps);
+            let fee_rate = core::FeeRate::from_u64(initial + offset as u64);
+            if last_fee_rate == Some(fee_rate) {
+                continue;
+            }
+            last_fee_rate = Some(fee_rate);
+            let tx = signer(tx_builder(fee_rate)?)?;
+            match self.send_transaction(tx, None) {
+                Err(err) if err.is_min_fee_rate_rejection() => {
+                    log::debug!("fee rate {} is too low, bump it", fee_rate);
+                    last_error = Some(err);
+                }
+                result => return result,
+            }
+        }
+        Err(last_error.expect("at least send once"))
+    }
+
+    //
+    // Module Miner
+    //
+
+    /// Polls `get_block_template` and calls the handler when a new template is produced.
+    ///
+    /// A template is new when its `work_id` or `current_time` changes. The loop stops and
+    /// returns `Ok(())` when the token is cancelled, the handler can cancel it too. It returns
+    /// the error when fetching the template fails.
+    pub fn watch_block_template<F>(
+        &self,
+        poll_interval: Duration,
+        token: CancellationToken,
+        mut handler: F,
+    ) -> Result<()>
+    where
+        F: FnMut(rpc::BlockTemplate),
+    {
+        let mut last = None;
+        while !token.is_cancelled() {
+            let template = self.get_block_template(None, None, None)?;
+            let current = Some((template.work_id, template.current_time));
+            if current != last && !token.is_cancelled() {
+                log::trace!("got a new block template {:?}", current);
+                last = current;
+                handler(template);
+            }
+            if !self.runtime().sleep_cancellable(poll_interval, &token) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Submits a block, and checks the returned hash against the local hash of the block.
+    pub fn submit_block_verified(
+        &self,
+        work_id: String,
+        block: packed::Block,
+    ) -> Result<fixed::H256> {
+        let expected = system::header_hash(&block.header());
+        let actual = self.submit_block(work_id, block)?;
+        if actual != expected {
+            return Err(Error::corruption(format!(
+                "submitted block {:#x} but the node returned {:#x}",
+                expected, actual
+            )));
+        }
+        Ok(actual)
+    }
+
+    //
+    // Module Stats
+    //
+
+    /// Collects the status of the node, for health checks.
+    ///
+    /// It's composed by `get_blockchain_info`, `get_tip_block_number`, `sync_state`,
+    /// `get_peers` and `tx_pool_info`, which are sent in one batch.
+    pub fn node_status(&self) -> Result<NodeStatus> {
+        let calls = vec![
+            batch_call("get_blockchain_info", ()),
+            batch_call("get_tip_block_number", ()),
+            batch_call("sync_state", ()),
+            batch_call("get_peers", ()),
+            batch_call("tx_pool_info", ()),
+        ];
+        let mut results = self.batch(calls)?.into_iter();
+        let mut next = || results.next().expect("a result for each call in the batch");
+        let chain_info: rpc::ChainInfo = parse_result(next())?;
+        let tip_block_number: rpc::BlockNumber = parse_result(next())?;
+        let sync_state: rpc::SyncState = parse_result(next())?;
+        let peers: Vec<rpc::RemoteNode> = parse_result(next())?;
+        let pool_info: rpc::TxPoolInfo = parse_result(next())?;
+        Ok(NodeStatus {
+            chain: chain_info.chain,
+            tip_block_number: tip_block_number.into(),
+            best_known_block_number: sync_state.best_known_block_number.into(),
+            is_initial_block_download: chain_info.is_initial_block_download,
+            peers_count: peers.len(),
+            pending_txs_count: pool_info.pending.into(),
+            proposed_txs_count: pool_info.proposed.into(),
+            orphan_txs_count: pool_info.orphan.into(),
+        })
+    }
+
+    /// Detects which network the node is in, the result is cached.
+    pub fn network_type(&self) -> Result<NetworkType> {
+        if let Some(network_type) = *self.network_type.read() {
+            return Ok(network_type);
+        }
+        let chain_info = self.get_blockchain_info()?;
+        let network_type = NetworkType::from_chain_name(&chain_info.chain);
+        log::debug!(
+            "network type of chain {} is {:?}",
+            chain_info.chain,
+            network_type
+        );
+        *self.network_type.write() = Some(network_type);
+        Ok(network_type)
+    }
+
+    /// Checks whether the node is in the initial block download.
+    pub fn is_ibd(&self) -> Result<bool> {
+        self.get_blockchain_info()
+            .map(|chain_info| chain_info.is_initial_block_download)
+    }
+
+    pub fn current_difficulty(&self) -> Result<fixed::U256> {
+        self.get_blockchain_info()
+            .map(|chain_info| chain_info.difficulty)
+    }
+
+    /// The median time of the last 37 blocks, in milliseconds.
+    pub fn median_time(&self) -> Result<u64> {
+        self.get_blockchain_info()
+            .map(|chain_info| chain_info.median_time.into())
+    }
+
+    //
+    // Module Net
+    //
+
+    fn node_version(&self) -> Result<(u64, u64, u64)> {
+        let node = self.node_info()?;
+        let version = parse_node_version(&node.version)?;
+        log::debug!("node version is {:?}", version);
+        Ok(version)
+    }
+
+    // Gets the local node info from the cache, unless it's expired.
+    fn node_info(&self) -> Result<rpc::LocalNode> {
+        if let Some((ref node, fetched_at)) = *self.node_info.read() {
+            if fetched_at.elapsed() < *self.node_info_ttl.read() {
+                return Ok(node.clone());
+            }
+        }
+        self.refresh_node_info()
+    }
+
+    /// Fetches the local node info and refreshes the cache.
+    pub fn refresh_node_info(&self) -> Result<rpc::LocalNode> {
+        let node = self.local_node_info()?;
+        *self.node_info.write() = Some((node.clone(), Instant::now()));
+        Ok(node)
+    }
+
+    /// Reconstructs the sync state of each peer from `sync_state` and `get_peers`.
+    ///
+    /// This is a best-effort replacement of the removed `get_peers_state`: peers which
+    /// have not started to sync with the local node yet are skipped.
+    pub fn peers_sync_state(&self) -> Result<Vec<PeerSyncState>> {
+        let local = self.sync_state()?;
+        let local_best_known_block_number = local.best_known_block_number.into();
+        let states = self
+            .get_peers()?
+            .into_iter()
+            .filter_map(|peer| {
+                let node_id = peer.node_id;
+                let is_outbound = peer.is_outbound;
+                peer.sync_state.map(|state| PeerSyncState {
+                    node_id,
+                    is_outbound,
+                    best_known_header_number: state.best_known_header_number.map(Into::into),
+                    last_common_header_number: state.last_common_header_number.map(Into::into),
+                    inflight_count: state.inflight_count.into(),
+                    can_fetch_count: state.can_fetch_count.into(),
+                    local_best_known_block_number,
+                })
+            })
+            .collect();
+        Ok(states)
+    }
+
+    /// Aggregates the connected peers by their versions and their directions.
+    pub fn peer_summary(&self) -> Result<PeerSummary> {
+        let peers = self.get_peers()?;
+        let mut versions = HashMap::new();
+        let mut outbound_count = 0;
+        let mut connected_millis = Vec::with_capacity(peers.len());
+        let mut ping_millis = Vec::with_capacity(peers.len());
+        for peer in peers {
+            *versions.entry(peer.version).or_insert(0) += 1;
+            if peer.is_outbound {
+                outbound_count += 1;
+            }
+            connected_millis.push(peer.connected_duration.value());
+            if let Some(duration) = peer.last_ping_duration {
+                ping_millis.push(duration.value());
+            }
+        }
+        let average = |millis: &[u64]| {
+            if millis.is_empty() {
+                None
+            } else {
+                let sum = millis.iter().map(|ms| u128::from(*ms)).sum::<u128>();
+                let average = sum / millis.len() as u128;
+                Some(Duration::from_millis(average as u64))
+            }
+        };
+        Ok(PeerSummary {
+            inbound_count: connected_millis.len() - outbound_count,
+            outbound_count,
+            versions,
+            average_connected_duration: average(&connected_millis),
+            average_ping_duration: average(&ping_millis),
+        })
+    }
+
+    /// Connects to a peer by a full address, such as "/ip4/1.2.3.4/tcp/8115/p2p/Qm...".
+    ///
+    /// The address is validated locally before sending, then the peer id is extracted from
+    /// the last "/p2p/" component and the remaining part is used as the address of the node.
+    /// An invalid address is rejected with the same invalid params error as the node returns.
+    pub fn add_peer(&self, mut addr: Multiaddr) -> Result<()> {
+        let peer_id = match addr.iter().last() {
+            Some(Protocol::P2P(peer_id)) => bs58::encode(peer_id).into_string(),
+            _ => {
+                return Err(Error::rpc_invalid_params(format!(
+                    "the last component of a peer address should be \"/p2p/{{peer_id}}\": {}",
+                    addr
+                )));
+            }
+        };
+        addr.pop();
+        let mut protos = addr.iter();
+        match protos.next() {
+            Some(Protocol::IP4(_))
+            | Some(Protocol::IP6(_))
+            | Some(Protocol::DNS4(_))
+            | Some(Protocol::DNS6(_)) => {}
+            _ => {
+                return Err(Error::rpc_invalid_params(format!(
+                    "a peer address should start with an ip or a dns component: {}",
+                    addr
+                )));
+            }
+        }
+        if !matches!(protos.next(), Some(Protocol::TCP(_))) {
+            return Err(Error::rpc_invalid_params(format!(
+                "a peer address should have a tcp component after the host: {}",
+                addr
+            )));
+        }
+        for proto in protos {
+            match proto {
+                Protocol::Ws | Protocol::Wss | Protocol::TLS(_) => {}
+                _ => {
+                    return Err(Error::rpc_invalid_params(format!(
+                        "unsupported component {} in the peer address {}",
+                        proto, addr
+                    )));
+                }
+            }
+        }
+        self.add_node(peer_id, addr.to_string())
+    }
+
+    /// Lists the supported protocols of the node as pairs of the name and the version.
+    ///
+    /// A protocol which supports several versions appears once for each version.
+    pub fn node_protocols(&self) -> Result<Vec<(String, String)>> {
+        let protocols = self
+            .local_node_info()?
+            .protocols
+            .into_iter()
+            .flat_map(|protocol| {
+                let name = protocol.name;
+                protocol
+                    .support_versions
+                    .into_iter()
+                    .map(move |version| (name.clone(), version))
+            })
+            .collect();
+        Ok(protocols)
+    }
+
+    pub fn connection_count(&self) -> Result<u64> {
+        self.local_node_info().map(|node| node.connections.into())
+    }
+
+    //
+    // Module Experiment
+    //
+
+    /// Gets the cycles of a transaction via `estimate_cycles`, or via `dry_run_transaction`
+    /// for the nodes which don't have `estimate_cycles`.
+    pub fn cycles_for(&self, tx: packed::Transaction) -> Result<core::Cycle> {
+        let result = match self.estimate_cycles(tx.clone()) {
+            Err(err) if err.is_method_not_found() => {
+                log::debug!("estimate_cycles is not found, fallback to dry_run_transaction");
+                self.dry_run_transaction_legacy(tx)?
+            }
+            result => result?,
+        };
+        Ok(result.cycles.into())
+    }
+
+    pub fn calculate_dao_maximum_withdraw_at_number(
+        &self,
+        out_point: packed::OutPoint,
+        block_number: core::BlockNumber,
+    ) -> Result<core::Capacity> {
+        let block_hash = if let Some(block_hash) = self.get_block_hash(block_number)? {
+            block_hash
+        } else {
+            let tip_number = self.get_tip_block_number()?;
+            return Err(Error::rpc_invalid_params(format!(
+                "block#{} is not found, the tip is block#{}",
+                block_number, tip_number
+            )));
+        };
+        self.calculate_dao_maximum_withdraw(out_point, block_hash)
+    }
+
+    /// Calculates the maximum withdraws of many deposits, the `calculate_dao_maximum_withdraw`
+    /// calls are sent in one JSON-RPC batch.
+    ///
+    /// The results are in the same order as the deposits, it fails if any deposit fails.
+    pub fn calculate_dao_maximum_withdraws(
+        &self,
+        pairs: Vec<(packed::OutPoint, fixed::H256)>,
+    ) -> Result<Vec<core::Capacity>> {
+        let calls = pairs
+            .into_iter()
+            .map(|(out_point, block_hash)| {
+                let params = (rpc::OutPoint::from(out_point), block_hash);
+                batch_call("calculate_dao_maximum_withdraw", params)
+            })
+            .collect();
+        self.batch(calls)?
+            .into_iter()
+            .map(|result| parse_result::<rpc::Capacity>(result).map(Into::into))
+            .collect()
+    }
+
+    //
+    // Module IntegrationTest
+    //
+
+    /// Processes a block without verification and without broadcasting, it only changes the
+    /// local node.
+    pub fn inject_block(&self, block: packed::Block) -> Result<Option<fixed::H256>> {
+        self.process_block_without_verify(block, false)
+    }
+
+    /// Processes a block without verification, then broadcasts it to the network.
+    pub fn inject_and_broadcast_block(&self, block: packed::Block) -> Result<Option<fixed::H256>> {
+        self.process_block_without_verify(block, true)
+    }
+
+    // Sends the calls in one JSON-RPC batch, the results are in the same order as the calls.
+    //
+    // The middlewares are applied to each call, but the identical calls are not shared.
+    // `get_consensus` is not in the RPC types of this version, so it's called through a batch
+    // and only `cellbase_maturity` is parsed.
+    fn cellbase_maturity(&self) -> Result<core::EpochNumberWithFraction> {
+        let result = self
+            .batch(vec![batch_call("get_consensus", ())])?
+            .pop()
+            .expect("a result for the call in the batch");
+        match result {
+            Err(ref err) if err.code == jsonrpc::ErrorCode::MethodNotFound => {
+                log::debug!("get_consensus is not found, use the default cellbase maturity");
+                Ok(system::DEFAULT_CELLBASE_MATURITY)
+            }
+            result => {
+                let maturity: rpc::Uint64 =
+                    parse_result(result.map(|consensus| consensus["cellbase_maturity"].clone()))?;
+                Ok(core::EpochNumberWithFraction::from_full_value(
+                    maturity.value(),
+                ))
+            }
+        }
+    }
+
+    fn batch(
+        &self,
+        calls: Vec<(String, jsonrpc::Params)>,
+    ) -> Result<Vec<jsonrpc::Result<jsonrpc::Value>>> {
+        let fut = self.http()?.batch(calls);
+        self.runtime().block_on_01(fut)
+    }
+}
+
+async fn poll_transaction_status(
+    http: HttpClient,
+    tx_hash: fixed::H256,
+    poll_interval: Duration,
+    sender: mpsc::UnboundedSender<Result<rpc::TxStatus>>,
+) {
+    let mut last: Option<rpc::TxStatus> = None;
+    loop {
+        let status = match http.get_transaction(tx_hash.clone()).compat().await {
+            Ok(tx) => tx.map(|tx| tx.tx_status),
+            Err(err) => {
+                let _ = sender.unbounded_send(Err(err));
+                return;
+            }
+        };
+        match status {
+            Some(status) if last.as_ref() != Some(&status) => {
+                log::trace!("transaction {:#x} status: {:?}", tx_hash, status);
+                let is_committed = status.status == rpc::Status::Committed;
+                if sender.unbounded_send(Ok(status.clone())).is_err() || is_committed {
+                    return;
+                }
+                last = Some(status);
+            }
+            None if last.is_some() => {
+                let err = Error::rpc_other(format!("transaction {:#x} is dropped", tx_hash));
+                let _ = sender.unbounded_send(Err(err));
+                return;
+            }
+            _ => {}
+        }
+        if sender.is_closed() {
+            return;
+        }
+        time::sleep(poll_interval).await;
+    }
+}
+
+// A call in a batch, the params should be a tuple, `()` for no params.
+fn batch_call<P: Serialize>(method: &str, params: P) -> (String, jsonrpc::Params) {
+    let params = match jsonrpc::serde_json::to_value(params) {
+        Ok(jsonrpc::Value::Array(values)) => jsonrpc::Params::Array(values),
+        Ok(jsonrpc::Value::Null) => jsonrpc::Params::Array(Vec::new()),
+        _ => panic!("the params of {} should be a tuple", method),
+    };
+    (method.to_owned(), params)
+}
+
+// Parses the result of a call in a batch.
+fn parse_result<T: DeserializeOwned>(result: jsonrpc::Result<jsonrpc::Value>) -> Result<T> {
+    jsonrpc::serde_json::from_value(result?).map_err(|err| {
+        Error::RpcTransport(format!(
+            "failed to parse the response as {}: {}",
+            std::any::type_name::<T>(),
+            err
+        ))
+    })
+}
+
+// The node version looks like "0.34.0 (f37f598 2020-07-17)".
+fn parse_node_version(input: &str) -> Result<(u64, u64, u64)> {
+    let parse_error = || Error::rpc_other(format!("failed to parse node version {:?}", input));
+    let mut parts = input
+        .split_whitespace()
+        .next()
+        .ok_or_else(parse_error)?
+        .splitn(3, '.')
+        .map(|part| {
+            // ignore the pre-release part, such as "0.39.0-rc1"
+            part.split('-')
+                .next()
+                .and_then(|part| part.parse::<u64>().ok())
+                .ok_or_else(parse_error)
+        });
+    let major = parts.next().ok_or_else(parse_error)??;
+    let minor = parts.next().ok_or_else(parse_error)??;
+    let patch = parts.next().ok_or_else(parse_error)??;
+    Ok((major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use futures::executor::block_on_stream;
+    use jsonrpc_core as jsonrpc;
+    use uckb_jsonrpc_core::types::utilities::CBMT;
+
+    use super::*;
+    use crate::mock::{to_value, MockHttpServer};
+
+    fn dry_run_result(cycles: u64) -> jsonrpc::Value {
+        to_value(rpc::DryRunResult {
+            cycles: cycles.into(),
+        })
+    }
+
+    // Each thread asks for its own block hash, the node answers slowly so the calls overlap.
+    #[test]
+    fn concurrent_calls_from_a_shared_client() {
+        let server = MockHttpServer::start(|_, params| {
+            let number: rpc::BlockNumber = params.clone().parse::<(_,)>()?.0;
+            thread::sleep(Duration::from_millis(100));
+            Ok(to_value(fixed::H256([number.value() as u8; 32])))
+        });
+        let client = Arc::new(server.client());
+        let threads = (0..8u64)
+            .map(|number| {
+                let client = Arc::clone(&client);
+                thread::spawn(move || client.get_block_hash(number).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for (number, thread) in threads.into_iter().enumerate() {
+            let hash = thread.join().unwrap();
+            assert_eq!(hash, Some(fixed::H256([number as u8; 32])));
+        }
+        assert_eq!(server.methods().len(), 8);
+    }
+
+    #[test]
+    fn cycles_for_falls_back_to_dry_run_transaction() {
+        let server = MockHttpServer::start(|method, _| match method {
+            "estimate_cycles" => Err(jsonrpc::Error::method_not_found()),
+            "dry_run_transaction" => Ok(dry_run_result(100)),
+            _ => Err(jsonrpc::Error::internal_error()),
+        });
+        let client = server.client();
+        let cycles = client.cycles_for(packed::Transaction::default()).unwrap();
+        assert_eq!(cycles, 100);
+        assert_eq!(
+            server.methods(),
+            vec![
+                "estimate_cycles".to_owned(),
+                "dry_run_transaction".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn dry_run_transaction_falls_back_to_estimate_cycles() {
+        let server = MockHttpServer::start(|method, _| match method {
+            "dry_run_transaction" => Err(jsonrpc::Error::method_not_found()),
+            "estimate_cycles" => Ok(dry_run_result(100)),
+            _ => Err(jsonrpc::Error::internal_error()),
+        });
+        let client = server.client();
+        let result = client
+            .dry_run_transaction(packed::Transaction::default())
+            .unwrap();
+        assert_eq!(result.cycles.value(), 100);
+        let result = client
+            .dry_run_transaction_cancellable(packed::Transaction::default(), Default::default())
+            .unwrap();
+        assert_eq!(result.cycles.value(), 100);
+        assert_eq!(
+            server.methods(),
+            vec![
+                "dry_run_transaction".to_owned(),
+                "estimate_cycles".to_owned(),
+                "dry_run_transaction".to_owned(),
+                "estimate_cycles".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn cycles_for_prefers_estimate_cycles() {
+        let server = MockHttpServer::start(|method, _| match method {
+            "estimate_cycles" => Ok(dry_run_result(200)),
+            _ => Err(jsonrpc::Error::internal_error()),
+        });
+        let client = server.client();
+        let cycles = client.cycles_for(packed::Transaction::default()).unwrap();
+        assert_eq!(cycles, 200);
+        assert_eq!(server.methods(), vec!["estimate_cycles".to_owned()]);
+    }
+
+    #[test]
+    fn cycles_for_does_not_fall_back_on_other_errors() {
+        let server = MockHttpServer::start(|_, _| Err(jsonrpc::Error::invalid_params("bad tx")));
+        let client = server.client();
+        let err = client
+            .cycles_for(packed::Transaction::default())
+            .unwrap_err();
+        assert!(!err.is_method_not_found());
+        assert_eq!(server.methods(), vec!["estimate_cycles".to_owned()]);
+    }
+
+    const PEER_ID: &str = "QmXS4Kbc9HEeykHUTJCm2tNmqghbvWyYpUp6BtE5b6VrAU";
+
+    #[test]
+    fn add_peer_splits_the_peer_id() {
+        let server = MockHttpServer::start(|_, _| Ok(jsonrpc::Value::Null));
+        let client = server.client();
+        let addr = format!("/ip4/10.0.0.1/tcp/8115/p2p/{}", PEER_ID);
+        client.add_peer(addr.parse().unwrap()).unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "add_node");
+        let expected: Vec<jsonrpc::Value> = vec![PEER_ID.into(), "/ip4/10.0.0.1/tcp/8115".into()];
+        assert_eq!(requests[0].params, jsonrpc::Params::Array(expected));
+    }
+
+    #[test]
+    fn add_peer_rejects_invalid_addresses_locally() {
+        let server = MockHttpServer::start(|_, _| Ok(jsonrpc::Value::Null));
+        let client = server.client();
+        let addrs = vec![
+            "/ip4/10.0.0.1/tcp/8115".to_owned(),
+            format!("/tcp/8115/p2p/{}", PEER_ID),
+            format!("/ip4/10.0.0.1/p2p/{}", PEER_ID),
+            format!("/ip4/10.0.0.1/tcp/8115/ip4/10.0.0.2/p2p/{}", PEER_ID),
+        ];
+        for addr in addrs {
+            let err = client.add_peer(addr.parse().unwrap()).unwrap_err();
+            assert!(
+                matches!(err, Error::RpcError(ref inner) if inner.code == jsonrpc::ErrorCode::InvalidParams),
+                "{}: {}",
+                addr,
+                err
+            );
+        }
+        assert!(server.requests().is_empty());
+    }
+
+    fn chain_info(chain: &str) -> jsonrpc::Value {
+        to_value(rpc::ChainInfo {
+            chain: chain.to_owned(),
+            median_time: 0.into(),
+            epoch: 0.into(),
+            difficulty: fixed::U256::zero(),
+            is_initial_block_download: false,
+            alerts: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn network_type_from_chain_name() {
+        assert_eq!(NetworkType::from_chain_name("ckb"), NetworkType::Mainnet);
+        assert_eq!(
+            NetworkType::from_chain_name("ckb_testnet"),
+            NetworkType::Testnet
+        );
+        assert_eq!(NetworkType::from_chain_name("ckb_dev"), NetworkType::Dev);
+        assert_eq!(NetworkType::from_chain_name(""), NetworkType::Dev);
+    }
+
+    #[test]
+    fn network_type_is_cached() {
+        let server = MockHttpServer::start(|_, _| Ok(chain_info("ckb_testnet")));
+        let client = server.client();
+        assert_eq!(client.network_type().unwrap(), NetworkType::Testnet);
+        assert_eq!(client.network_type().unwrap(), NetworkType::Testnet);
+        assert_eq!(server.methods(), vec!["get_blockchain_info".to_owned()]);
+    }
+
+    fn remote_node(node_id: &str) -> rpc::RemoteNode {
+        rpc::RemoteNode {
+            version: "0.38.0".to_owned(),
+            node_id: node_id.to_owned(),
+            addresses: Vec::new(),
+            is_outbound: true,
+            connected_duration: 0.into(),
+            last_ping_duration: None,
+            sync_state: None,
+            protocols: Vec::new(),
+        }
+    }
+
+    // Each field is taken from a different value, so a mixed up field is caught.
+    #[test]
+    fn node_status_is_collected_in_one_batch() {
+        let server = MockHttpServer::start(|method, _| match method {
+            "get_blockchain_info" => Ok(chain_info("ckb_testnet")),
+            "get_tip_block_number" => Ok(to_value(rpc::BlockNumber::from(100))),
+            "sync_state" => Ok(to_value(rpc::SyncState {
+                ibd: false,
+                best_known_block_number: 200.into(),
+                best_known_block_timestamp: 0.into(),
+                orphan_blocks_count: 0.into(),
+                inflight_blocks_count: 0.into(),
+                fast_time: 0.into(),
+                normal_time: 0.into(),
+                low_time: 0.into(),
+            })),
+            "get_peers" => Ok(to_value(vec![remote_node("a"), remote_node("b")])),
+            "tx_pool_info" => Ok(to_value(rpc::TxPoolInfo {
+                tip_hash: fixed::H256::default(),
+                tip_number: 100.into(),
+                pending: 3.into(),
+                proposed: 4.into(),
+                orphan: 5.into(),
+                total_tx_size: 0.into(),
+                total_tx_cycles: 0.into(),
+                min_fee_rate: 1000.into(),
+                last_txs_updated_at: 0.into(),
+            })),
+            _ => Err(jsonrpc::Error::method_not_found()),
+        });
+        let client = server.client();
+        let status = client.node_status().unwrap();
+        assert_eq!(status.chain, "ckb_testnet");
+        assert_eq!(status.tip_block_number, 100);
+        assert_eq!(status.best_known_block_number, 200);
+        assert!(!status.is_initial_block_download);
+        assert_eq!(status.peers_count, 2);
+        assert_eq!(status.pending_txs_count, 3);
+        assert_eq!(status.proposed_txs_count, 4);
+        assert_eq!(status.orphan_txs_count, 5);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        assert!(requests.iter().all(|request| request.in_batch));
+    }
+
+    fn min_fee_rate_rejection() -> jsonrpc::Error {
+        jsonrpc::Error {
+            code: jsonrpc::ErrorCode::ServerError(-1104),
+            message: "PoolRejectedTransactionByMinFeeRate".to_owned(),
+            data: None,
+        }
+    }
+
+    // Sends with the fee rate bumping, the node accepts the transaction after `rejections`
+    // rejections. Returns the result and the fee rates which are used.
+    fn send_with_fee_bump(
+        rejections: usize,
+        initial: u64,
+        max: u64,
+        steps: u64,
+    ) -> (Result<fixed::H256>, Vec<u64>) {
+        let count = std::sync::atomic::AtomicUsize::new(0);
+        let server = MockHttpServer::start(move |_, _| {
+            if count.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < rejections {
+                Err(min_fee_rate_rejection())
+            } else {
+                Ok(to_value(fixed::H256::default()))
+            }
+        });
+        let client = server.client();
+        let mut fee_rates = Vec::new();
+        let result = client.send_transaction_with_fee_bump(
+            |fee_rate| {
+                fee_rates.push(fee_rate.as_u64());
+                Ok(packed::Transaction::default())
+            },
+            Ok,
+            core::FeeRate::from_u64(initial),
+            core::FeeRate::from_u64(max),
+            steps,
+        );
+        (result, fee_rates)
+    }
+
+    #[test]
+    fn fee_bump_stops_at_the_max_fee_rate() {
+        let (result, fee_rates) = send_with_fee_bump(usize::MAX, 1000, 1003, 10);
+        assert!(result.unwrap_err().is_min_fee_rate_rejection());
+        assert_eq!(fee_rates, vec![1000, 1001, 1002, 1003]);
+    }
+
+    #[test]
+    fn fee_bump_returns_the_accepted_hash() {
+        let (result, fee_rates) = send_with_fee_bump(2, 1000, 2000, 4);
+        assert_eq!(result.unwrap(), fixed::H256::default());
+        assert_eq!(fee_rates, vec![1000, 1250, 1500]);
+    }
+
+    #[test]
+    fn fee_bump_does_not_overflow() {
+        let (result, fee_rates) = send_with_fee_bump(usize::MAX, 0, u64::MAX, 4);
+        assert!(result.is_err());
+        let expected = (0..=4)
+            .map(|step| (u128::from(u64::MAX) * step / 4) as u64)
+            .collect::<Vec<_>>();
+        assert_eq!(fee_rates, expected);
+    }
+
+    #[test]
+    fn fee_bump_sends_once_without_a_range() {
+        let (result, fee_rates) = send_with_fee_bump(usize::MAX, 1000, 1000, 3);
+        assert!(result.is_err());
+        assert_eq!(fee_rates, vec![1000]);
+    }
+
+    // A sample of `local_node_info` with several protocols.
+    fn local_node() -> jsonrpc::Value {
+        jsonrpc::serde_json::json!({
+            "version": "0.38.0",
+            "node_id": PEER_ID,
+            "active": true,
+            "addresses": [{ "address": "/ip4/10.0.0.1/tcp/8115", "score": "0xff" }],
+            "protocols": [
+                { "id": "0x0", "name": "/ckb/ping", "support_versions": ["0.0.1"] },
+                { "id": "0x2", "name": "/ckb/identify", "support_versions": [] },
+                { "id": "0x64", "name": "/ckb/syn", "support_versions": ["1", "2"] },
+            ],
+            "connections": "0xb",
+        })
+    }
+
+    #[test]
+    fn node_protocols_and_connection_count() {
+        let server = MockHttpServer::start(|_, _| Ok(local_node()));
+        let client = server.client();
+        let protocols = client.node_protocols().unwrap();
+        let expected = vec![
+            ("/ckb/ping".to_owned(), "0.0.1".to_owned()),
+            ("/ckb/syn".to_owned(), "1".to_owned()),
+            ("/ckb/syn".to_owned(), "2".to_owned()),
+        ];
+        assert_eq!(protocols, expected);
+        assert_eq!(client.connection_count().unwrap(), 11);
+    }
+
+    #[test]
+    fn get_live_cells_checked_filters_out_dead_cells() {
+        let server = MockHttpServer::start(|_, params| {
+            let (out_point, _) = params.clone().parse::<(rpc::OutPoint, bool)>()?;
+            let status = if out_point.index.value() == 1 {
+                "dead"
+            } else {
+                "live"
+            };
+            Ok(to_value(rpc::CellWithStatus {
+                cell: None,
+                status: status.to_owned(),
+            }))
+        });
+        let client = server.client();
+        let out_points = (0..3)
+            .map(|index| packed::OutPoint::new(Default::default(), index))
+            .collect::<Vec<_>>();
+        let live = client.get_live_cells_checked(&out_points).unwrap();
+        assert_eq!(live, vec![out_points[0].clone(), out_points[2].clone()]);
+        assert_eq!(server.methods(), vec!["get_live_cell".to_owned(); 3]);
+    }
+
+    // A block with a cellbase and a transfer, its header commits to the body.
+    fn sample_block() -> core::BlockView {
+        let cellbase = core::TransactionBuilder::default()
+            .input(packed::CellInput::new_cellbase_input(1))
+            .witness(Default::default())
+            .build();
+        let transfer = core::TransactionBuilder::default()
+            .input(packed::CellInput::new(
+                packed::OutPoint::new(cellbase.hash(), 0),
+                0,
+            ))
+            .output(Default::default())
+            .output_data(Default::default())
+            .witness([1u8; 85][..].pack())
+            .build();
+        core::BlockBuilder::default()
+            .number(1.pack())
+            .transaction(cellbase)
+            .transaction(transfer)
+            .build()
+    }
+
+    fn serve_block(block: packed::Block) -> MockHttpServer {
+        MockHttpServer::start(move |_, _| {
+            Ok(to_value(rpc::JsonBytes::from_bytes(block.as_bytes())))
+        })
+    }
+
+    #[test]
+    fn get_block_verified_accepts_a_valid_block() {
+        let block = sample_block();
+        let server = serve_block(block.data());
+        let client = server.client();
+        let verified = client
+            .get_block_verified(block.hash().unpack())
+            .unwrap()
+            .unwrap();
+        assert_eq!(verified.data().as_slice(), block.data().as_slice());
+    }
+
+    #[test]
+    fn get_block_verified_rejects_a_tampered_body() {
+        let block = sample_block();
+        let transactions = block
+            .data()
+            .transactions()
+            .as_builder()
+            .push(packed::Transaction::default())
+            .build();
+        let tampered = block.data().as_builder().transactions(transactions).build();
+        let server = serve_block(tampered);
+        let client = server.client();
+        let result = client.get_block_verified(block.hash().unpack());
+        assert!(matches!(result, Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn get_block_verified_rejects_another_block() {
+        let server = serve_block(sample_block().data());
+        let client = server.client();
+        let result = client.get_block_verified(fixed::H256::default());
+        assert!(matches!(result, Err(Error::Corruption(_))));
+    }
+
+    fn live_output() -> packed::CellOutput {
+        packed::CellOutput::new_builder()
+            .capacity(core::Capacity::bytes(100).unwrap().pack())
+            .build()
+    }
+
+    // The status of a cell is decided by the index of its out point.
+    fn cell_status(index: u32) -> rpc::CellWithStatus {
+        let (cell, status) = match index {
+            0 => {
+                let info = rpc::CellInfo {
+                    output: live_output().into(),
+                    data: None,
+                };
+                (Some(info), "live")
+            }
+            1 => (None, "dead"),
+            2 => (None, "unknown"),
+            3 => (None, "live"),
+            _ => (None, "pending"),
+        };
+        rpc::CellWithStatus {
+            cell,
+            status: status.to_owned(),
+        }
+    }
+
+    #[test]
+    fn out_point_status_of_each_cell_status() {
+        let server = MockHttpServer::start(|_, params| {
+            let (out_point, _) = params.clone().parse::<(rpc::OutPoint, bool)>()?;
+            Ok(to_value(cell_status(out_point.index.value())))
+        });
+        let client = server.client();
+        let status =
+            |index| client.out_point_status(&packed::OutPoint::new(Default::default(), index));
+        assert_eq!(status(0).unwrap(), OutPointStatus::Live(live_output()));
+        assert_eq!(status(1).unwrap(), OutPointStatus::Dead);
+        assert_eq!(status(2).unwrap(), OutPointStatus::Unknown);
+        // a live cell without the output, and an unknown status
+        assert!(matches!(status(3), Err(Error::RpcError(_))));
+        assert!(matches!(status(4), Err(Error::RpcError(_))));
+    }
+
+    #[test]
+    fn verify_transaction_inclusion_against_trusted_headers() {
+        let block = sample_block();
+        let proof = CBMT::build_merkle_proof(block.tx_hashes(), &[1]).unwrap();
+        let proof = rpc::TransactionProof {
+            block_hash: block.hash().unpack(),
+            witnesses_root: block.calc_witnesses_root().unpack(),
+            proof: rpc::MerkleProof {
+                indices: proof
+                    .indices()
+                    .iter()
+                    .map(|index| (*index).into())
+                    .collect(),
+                lemmas: proof.lemmas().iter().map(|lemma| lemma.unpack()).collect(),
+            },
+        };
+        let server = MockHttpServer::start(move |_, _| Ok(to_value(&proof)));
+        let client = server.client();
+        let header = block.header();
+        let tx_hash: fixed::H256 = block.tx_hashes()[1].unpack();
+        assert!(client
+            .verify_transaction_inclusion(tx_hash.clone(), &header)
+            .unwrap());
+        let other_header = header.as_advanced_builder().number(2.pack()).build();
+        assert!(!client
+            .verify_transaction_inclusion(tx_hash, &other_header)
+            .unwrap());
+        // the proof is for another transaction
+        let other: fixed::H256 = block.tx_hashes()[0].unpack();
+        assert!(!client.verify_transaction_inclusion(other, &header).unwrap());
+        assert_eq!(
+            server.methods(),
+            vec!["get_transaction_proof".to_owned(); 3]
+        );
+    }
+
+    #[test]
+    fn get_block_hashes_matches_the_responses_by_ids() {
+        // the tip is 10, the hash of a block is filled with its number
+        let server = MockHttpServer::start(|_, params| {
+            let (number,) = params.clone().parse::<(rpc::BlockNumber,)>()?;
+            let number: core::BlockNumber = number.into();
+            let hash = if number <= 10 {
+                Some(fixed::H256([number as u8; 32]))
+            } else {
+                None
+            };
+            Ok(to_value(hash))
+        });
+        let client = server.client();
+        let hashes = client.get_block_hashes(&[7, 11, 0, 10, 100]).unwrap();
+        let expected = vec![
+            Some(fixed::H256([7; 32])),
+            None,
+            Some(fixed::H256([0; 32])),
+            Some(fixed::H256([10; 32])),
+            None,
+        ];
+        assert_eq!(hashes, expected);
+        // one batch, which is answered in the reversed order
+        let requests = server.requests();
+        assert!(requests.iter().all(|req| req.in_batch));
+        let numbers = requests
+            .into_iter()
+            .map(|req| {
+                let (number,) = req.params.parse::<(rpc::BlockNumber,)>().unwrap();
+                number.into()
+            })
+            .collect::<Vec<core::BlockNumber>>();
+        assert_eq!(numbers, vec![100, 10, 0, 11, 7]);
+        assert_eq!(server.methods(), vec!["get_block_hash".to_owned(); 5]);
+        // nothing is sent for an empty batch
+        assert!(client.get_block_hashes(&[]).unwrap().is_empty());
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[test]
+    fn get_block_hashes_fails_on_a_node_error() {
+        let server = MockHttpServer::start(|_, params| {
+            let (number,) = params.clone().parse::<(rpc::BlockNumber,)>()?;
+            if number.value() == 1 {
+                Err(jsonrpc::Error::internal_error())
+            } else {
+                Ok(jsonrpc::Value::Null)
+            }
+        });
+        let client = server.client();
+        let err = client.get_block_hashes(&[0, 1, 2]).unwrap_err();
+        assert!(matches!(err, Error::RpcError(_)), "{}", err);
+    }
+
+    #[test]
+    fn calculate_dao_maximum_withdraws_keeps_the_order() {
+        // the maximum withdraw is the index of the deposit plus 1000
+        let server = MockHttpServer::start(|_, params| {
+            let (out_point, _) = params.clone().parse::<(rpc::OutPoint, fixed::H256)>()?;
+            Ok(to_value(rpc::Capacity::from(
+                1000 + out_point.index.value() as u64,
+            )))
+        });
+        let client = server.client();
+        let pairs = [2, 0, 1]
+            .iter()
+            .map(|index| {
+                let out_point = packed::OutPoint::new(Default::default(), *index);
+                (out_point, fixed::H256::default())
+            })
+            .collect();
+        let withdraws = client.calculate_dao_maximum_withdraws(pairs).unwrap();
+        let expected = [1002, 1000, 1001]
+            .iter()
+            .map(|shannons| core::Capacity::shannons(*shannons))
+            .collect::<Vec<_>>();
+        assert_eq!(withdraws, expected);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|req| req.in_batch));
+    }
+
+    // A transaction with the status, `None` for an unknown transaction.
+    fn tx_with_status(status: Option<rpc::TxStatus>) -> jsonrpc::Value {
+        to_value(status.map(|tx_status| rpc::TransactionWithStatus {
+            transaction: packed::Transaction::default().into_view().into(),
+            tx_status,
+        }))
+    }
+
+    // Returns the statuses one by one, the last one is repeated.
+    fn status_sequence(statuses: Vec<Option<rpc::TxStatus>>) -> impl Fn() -> jsonrpc::Value {
+        let count = AtomicUsize::new(0);
+        move || {
+            let index = count.fetch_add(1, Ordering::SeqCst).min(statuses.len() - 1);
+            tx_with_status(statuses[index].clone())
+        }
+    }
+
+    #[test]
+    fn watch_transaction_yields_the_changes_until_committed() {
+        let committed = rpc::TxStatus::committed(fixed::H256([1; 32]));
+        let next = status_sequence(vec![
+            None,
+            Some(rpc::TxStatus::pending()),
+            Some(rpc::TxStatus::pending()),
+            Some(rpc::TxStatus::proposed()),
+            Some(rpc::TxStatus::proposed()),
+            Some(committed.clone()),
+        ]);
+        let server = MockHttpServer::start(move |_, _| Ok(next()));
+        let client = server.client();
+        let stream = client.watch_transaction(Default::default(), Duration::from_millis(1));
+        let statuses = block_on_stream(stream).collect::<Result<Vec<_>>>().unwrap();
+        let expected = vec![
+            rpc::TxStatus::pending(),
+            rpc::TxStatus::proposed(),
+            committed,
+        ];
+        assert_eq!(statuses, expected);
+        // the polling stops after the transaction is committed
+        assert_eq!(server.methods(), vec!["get_transaction".to_owned(); 6]);
+    }
+
+    #[test]
+    fn watch_transaction_ends_with_an_error_when_dropped() {
+        let next = status_sequence(vec![
+            Some(rpc::TxStatus::pending()),
+            Some(rpc::TxStatus::pending()),
+            None,
+        ]);
+        let server = MockHttpServer::start(move |_, _| Ok(next()));
+        let client = server.client();
+        let stream = client.watch_transaction(Default::default(), Duration::from_millis(1));
+        let mut items = block_on_stream(stream);
+        assert_eq!(items.next().unwrap().unwrap(), rpc::TxStatus::pending());
+        let err = items.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("dropped"), "{}", err);
+        assert!(items.next().is_none());
+        assert_eq!(server.methods(), vec!["get_transaction".to_owned(); 3]);
+    }
+
+    fn block_template(work_id: u64, current_time: u64) -> jsonrpc::Value {
+        to_value(rpc::BlockTemplate {
+            version: 0.into(),
+            compact_target: 0.into(),
+            current_time: current_time.into(),
+            number: 1.into(),
+            epoch: 0.into(),
+            parent_hash: Default::default(),
+            cycles_limit: 0.into(),
+            bytes_limit: 0.into(),
+            uncles_count_limit: 0.into(),
+            uncles: Vec::new(),
+            transactions: Vec::new(),
+            proposals: Vec::new(),
+            cellbase: Default::default(),
+            work_id: work_id.into(),
+            dao: Default::default(),
+        })
+    }
+
+    #[test]
+    fn watch_block_template_calls_the_handler_for_new_templates() {
+        let templates = [(1, 100), (1, 100), (2, 100), (2, 100), (2, 101), (3, 102)];
+        let count = AtomicUsize::new(0);
+        let server = MockHttpServer::start(move |_, _| {
+            let index = count
+                .fetch_add(1, Ordering::SeqCst)
+                .min(templates.len() - 1);
+            let (work_id, current_time) = templates[index];
+            Ok(block_template(work_id, current_time))
+        });
+        let client = server.client();
+        let token = CancellationToken::new();
+        let mut seen = Vec::new();
+        client
+            .watch_block_template(Duration::from_millis(1), token.clone(), |template| {
+                seen.push((template.work_id.value(), template.current_time.value()));
+                if seen.len() == 3 {
+                    token.cancel();
+                }
+            })
+            .unwrap();
+        assert_eq!(seen, vec![(1, 100), (2, 100), (2, 101)]);
+        // stops right after the token is cancelled
+        assert_eq!(server.methods(), vec!["get_block_template".to_owned(); 5]);
+    }
+
+    #[test]
+    fn watch_block_template_stops_on_errors() {
+        let server = MockHttpServer::start(|_, _| Err(jsonrpc::Error::internal_error()));
+        let client = server.client();
+        let token = CancellationToken::new();
+        let result = client.watch_block_template(Duration::from_millis(1), token, |_| {
+            panic!("no templates");
+        });
+        assert!(matches!(result, Err(Error::RpcError(_))));
+    }
+
+    #[test]
+    fn watch_block_template_does_nothing_after_cancelled() {
+        let server = MockHttpServer::start(|_, _| Ok(block_template(1, 100)));
+        let client = server.client();
+        let token = CancellationToken::new();
+        token.cancel();
+        client
+            .watch_block_template(Duration::from_millis(1), token, |_| {
+                panic!("cancelled");
+            })
+            .unwrap();
+        assert!(server.requests().is_empty());
+    }
+
+    // A node which answers `get_transaction` by the statuses in order, the transaction is
+    // committed in the blocks of the headers, and the tips are returned in order too.
+    fn transaction_node(
+        statuses: Vec<Option<rpc::TxStatus>>,
+        headers: Vec<core::HeaderView>,
+        tips: Vec<core::BlockNumber>,
+    ) -> MockHttpServer {
+        let next_status = status_sequence(statuses);
+        let tip_count = AtomicUsize::new(0);
+        MockHttpServer::start(move |method, params| {
+            let first_param = || match params {
+                jsonrpc::Params::Array(values) => values[0].clone(),
+                _ => panic!("params should be an array"),
+            };
+            match method {
+                "get_transaction" => Ok(next_status()),
+                "get_header" => {
+                    let hash: fixed::H256 = jsonrpc::serde_json::from_value(first_param()).unwrap();
+                    let header = headers.iter().find(|header| {
+                        let header_hash: fixed::H256 = header.hash().unpack();
+                        header_hash == hash
+                    });
+                    Ok(to_value(header.cloned().map(rpc::HeaderView::from)))
+                }
+                "get_block_hash" => {
+                    let number: rpc::BlockNumber =
+                        jsonrpc::serde_json::from_value(first_param()).unwrap();
+                    let hash = headers
+                        .iter()
+                        .find(|header| header.number() == number.value())
+                        .map(|header| header.hash().unpack());
+                    Ok(to_value::<Option<fixed::H256>>(hash))
+                }
+                "get_tip_block_number" => {
+                    let index = tip_count.fetch_add(1, Ordering::SeqCst).min(tips.len() - 1);
+                    Ok(to_value(rpc::BlockNumber::from(tips[index])))
+                }
+                _ => Err(jsonrpc::Error::method_not_found()),
+            }
+        })
+    }
+
+    fn header_at(number: core::BlockNumber) -> core::HeaderView {
+        core::HeaderBuilder::default().number(number.pack()).build()
+    }
+
+    fn header_in_epoch(
+        number: core::BlockNumber,
+        epoch: core::EpochNumberWithFraction,
+    ) -> core::HeaderView {
+        core::HeaderBuilder::default()
+            .number(number.pack())
+            .epoch(epoch.full_value().pack())
+            .build()
+    }
+
+    // The cellbase is committed at epoch 10 and a half, the node answers `get_consensus` with
+    // `consensus`.
+    fn cellbase_node(
+        tip_epoch: core::EpochNumberWithFraction,
+        consensus: jsonrpc::Result<jsonrpc::Value>,
+    ) -> MockHttpServer {
+        let block = header_in_epoch(1000, core::EpochNumberWithFraction::new(10, 500, 1000));
+        let tip = header_in_epoch(2000, tip_epoch);
+        let cellbase = core::TransactionBuilder::default()
+            .input(packed::CellInput::new_cellbase_input(1000))
+            .build();
+        MockHttpServer::start(move |method, _| match method {
+            "get_transaction" => Ok(to_value(Some(rpc::TransactionWithStatus {
+                transaction: cellbase.clone().into(),
+                tx_status: rpc::TxStatus::committed(block.hash().unpack()),
+            }))),
+            "get_header" => Ok(to_value(Some(rpc::HeaderView::from(block.clone())))),
+            "get_tip_header" => Ok(to_value(rpc::HeaderView::from(tip.clone()))),
+            "get_consensus" => consensus.clone(),
+            _ => Err(jsonrpc::Error::method_not_found()),
+        })
+    }
+
+    fn consensus(
+        cellbase_maturity: core::EpochNumberWithFraction,
+    ) -> jsonrpc::Result<jsonrpc::Value> {
+        Ok(jsonrpc::serde_json::json!({
+            "id": "ckb_dev",
+            "cellbase_maturity": rpc::Uint64::from(cellbase_maturity.full_value()),
+        }))
+    }
+
+    #[test]
+    fn cellbase_maturity_is_read_from_the_consensus() {
+        let maturity = core::EpochNumberWithFraction::new(0, 1, 10);
+        // 10.5 + 0.1 = 10.6
+        let cases = [
+            (core::EpochNumberWithFraction::new(10, 599, 1000), false),
+            (core::EpochNumberWithFraction::new(10, 600, 1000), true),
+        ];
+        for (tip_epoch, is_mature) in cases.iter() {
+            let server = cellbase_node(*tip_epoch, consensus(maturity));
+            let client = server.client();
+            let out_point = packed::OutPoint::new(Default::default(), 0);
+            assert_eq!(client.is_cellbase_mature(&out_point).unwrap(), *is_mature);
+            assert!(server.methods().contains(&"get_consensus".to_owned()));
+        }
+    }
+
+    #[test]
+    fn cellbase_maturity_falls_back_to_the_default() {
+        // 10.5 + 4 = 14.5
+        let cases = [
+            (core::EpochNumberWithFraction::new(14, 499, 1000), false),
+            (core::EpochNumberWithFraction::new(14, 500, 1000), true),
+        ];
+        for (tip_epoch, is_mature) in cases.iter() {
+            let server = cellbase_node(*tip_epoch, Err(jsonrpc::Error::method_not_found()));
+            let client = server.client();
+            let out_point = packed::OutPoint::new(Default::default(), 0);
+            assert_eq!(client.is_cellbase_mature(&out_point).unwrap(), *is_mature);
+        }
+        let server = cellbase_node(
+            core::EpochNumberWithFraction::new(20, 0, 1),
+            Err(jsonrpc::Error::internal_error()),
+        );
+        let client = server.client();
+        let out_point = packed::OutPoint::new(Default::default(), 0);
+        assert!(client.is_cellbase_mature(&out_point).is_err());
+    }
+
+    #[test]
+    fn wait_for_transaction_restarts_after_a_reorg() {
+        let (first, second) = (header_at(10), header_at(11));
+        let statuses = vec![
+            Some(rpc::TxStatus::pending()),
+            Some(rpc::TxStatus::committed(first.hash().unpack())),
+            // rolled back by a reorg, then committed in another block
+            Some(rpc::TxStatus::pending()),
+            Some(rpc::TxStatus::committed(second.hash().unpack())),
+        ];
+        // block#10 is not confirmed when the tip is block#11
+        let server = transaction_node(statuses, vec![first, second.clone()], vec![11, 13]);
+        let client = server.client();
+        let (number, hash) = client
+            .wait_for_transaction(
+                Default::default(),
+                2,
+                Duration::from_millis(1),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        assert_eq!(number, 11);
+        assert_eq!(hash, second.hash().unpack());
+        let polls = server
+            .methods()
+            .into_iter()
+            .filter(|method| method == "get_transaction")
+            .count();
+        assert_eq!(polls, 4);
+    }
+
+    #[test]
+    fn wait_for_transaction_fails_if_dropped() {
+        let statuses = vec![Some(rpc::TxStatus::pending()), None];
+        let server = transaction_node(statuses, Vec::new(), vec![0]);
+        let client = server.client();
+        let err = client
+            .wait_for_transaction(
+                Default::default(),
+                0,
+                Duration::from_millis(1),
+                Duration::from_secs(5),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("dropped"), "{}", err);
+    }
+
+    #[test]
+    fn wait_for_transaction_does_not_overflow() {
+        let header = header_at(10);
+        let statuses = vec![Some(rpc::TxStatus::committed(header.hash().unpack()))];
+        let server = transaction_node(statuses, vec![header], vec![u64::MAX]);
+        let client = server.client();
+        let err = client
+            .wait_for_transaction(
+                Default::default(),
+                u64::MAX,
+                Duration::from_millis(1),
+                Duration::from_millis(20),
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)), "{}", err);
+    }
+}
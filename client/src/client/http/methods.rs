@@ -6,8 +6,11 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use std::sync::Once;
+
 use jsonrpc_core::futures::Future;
-use uckb_jsonrpc_core::types::{core, fixed, packed, rpc};
+use tokio_util::sync::CancellationToken;
+use uckb_jsonrpc_core::types::{core, fixed, packed, prelude::*, rpc};
 
 use super::HttpClient;
 use crate::{
@@ -15,6 +18,9 @@ use crate::{
     Client,
 };
 
+// Warn the deprecation of `dry_run_transaction` only once.
+static DRY_RUN_TRANSACTION_DEPRECATED: Once = Once::new();
+
 // block on future
 macro_rules! b {
     ($self:ident, $method:ident $( ,$param:ident )*) => {{
@@ -27,15 +33,29 @@ macro_rules! b {
     }};
 }
 
+// block on future, which could be cancelled by a token
+macro_rules! bc {
+    ($self:ident, $token:ident, $method:ident $( ,$param:ident )*) => {{
+        bc!($self, $token, $method, $( $param, )* )
+    }};
+    ($self:ident, $token:ident, $method:ident, $( $param:ident, )*) => {{
+        let rt = $self.runtime();
+        let fut = $self.http()?.$method( $( $param, )* );
+        rt.block_on_01_cancellable(fut, $token)
+            .unwrap_or(Err(Error::Cancelled))
+    }};
+}
+
 // convert future
 macro_rules! c{
     ($self:ident, $method:ident $( ,$param:expr )*) => {{
         c!($self, $method, $( $param, )* )
     }};
     ($self:ident, $method:ident, $( $param:expr, )*) => {{
-        $self.client()
+        let (client, failure) = $self.client();
+        client
             .$method( $( $param, )* )
-            .map_err(Into::into)
+            .map_err(move |err| failure.into_error(err))
     }};
 }
 
@@ -69,6 +89,15 @@ impl Client {
         b!(self, get_block, block_hash, verbosity)
     }
 
+    pub fn get_block_cancellable(
+        &self,
+        block_hash: fixed::H256,
+        verbosity: Option<u32>,
+        token: CancellationToken,
+    ) -> Result<Option<core::BlockView>> {
+        bc!(self, token, get_block, block_hash, verbosity)
+    }
+
     pub fn get_block_by_number(
         &self,
         block_number: core::BlockNumber,
@@ -77,6 +106,26 @@ impl Client {
         b!(self, get_block_by_number, block_number, verbosity)
     }
 
+    pub fn get_block_by_number_cancellable(
+        &self,
+        block_number: core::BlockNumber,
+        verbosity: Option<u32>,
+        token: CancellationToken,
+    ) -> Result<Option<core::BlockView>> {
+        bc!(self, token, get_block_by_number, block_number, verbosity)
+    }
+
+    pub fn get_block_packed(&self, block_hash: fixed::H256) -> Result<Option<packed::Block>> {
+        b!(self, get_packed_block, block_hash)
+    }
+
+    pub fn get_block_by_number_packed(
+        &self,
+        block_number: core::BlockNumber,
+    ) -> Result<Option<packed::Block>> {
+        b!(self, get_packed_block_by_number, block_number)
+    }
+
     pub fn get_header(
         &self,
         block_hash: fixed::H256,
@@ -100,6 +149,14 @@ impl Client {
         b!(self, get_transaction, tx_hash)
     }
 
+    pub fn get_transaction_cancellable(
+        &self,
+        tx_hash: fixed::H256,
+        token: CancellationToken,
+    ) -> Result<Option<rpc::TransactionWithStatus>> {
+        bc!(self, token, get_transaction, tx_hash)
+    }
+
     pub fn get_block_hash(&self, block_number: core::BlockNumber) -> Result<Option<fixed::H256>> {
         b!(self, get_block_hash, block_number)
     }
@@ -146,6 +203,15 @@ impl Client {
         b!(self, get_transaction_proof, tx_hashes, block_hash)
     }
 
+    pub fn get_transaction_proof_cancellable(
+        &self,
+        tx_hashes: Vec<fixed::H256>,
+        block_hash: Option<fixed::H256>,
+        token: CancellationToken,
+    ) -> Result<rpc::TransactionProof> {
+        bc!(self, token, get_transaction_proof, tx_hashes, block_hash)
+    }
+
     pub fn verify_transaction_proof(
         &self,
         tx_proof: rpc::TransactionProof,
@@ -275,10 +341,63 @@ impl Client {
     // Module Experiment
     //
 
+    /// Deprecated by the node, use `estimate_cycles` or `cycles_for` instead.
+    ///
+    /// A warning is logged at the first call. If the node doesn't have
+    /// `dry_run_transaction` any more, `estimate_cycles` is called instead, which returns
+    /// the same result.
+    #[deprecated(note = "use estimate_cycles or cycles_for")]
     pub fn dry_run_transaction(&self, tx: packed::Transaction) -> Result<rpc::DryRunResult> {
+        warn_dry_run_transaction_deprecated();
+        let tx_cloned = tx.clone();
+        match b!(self, dry_run_transaction, tx_cloned) {
+            Err(err) if err.is_method_not_found() => {
+                log::debug!("dry_run_transaction is not found, fallback to estimate_cycles");
+                self.estimate_cycles(tx)
+            }
+            result => result,
+        }
+    }
+
+    /// Deprecated by the node, same as `dry_run_transaction`.
+    #[deprecated(note = "use estimate_cycles_cancellable or cycles_for")]
+    pub fn dry_run_transaction_cancellable(
+        &self,
+        tx: packed::Transaction,
+        token: CancellationToken,
+    ) -> Result<rpc::DryRunResult> {
+        warn_dry_run_transaction_deprecated();
+        let tx_cloned = tx.clone();
+        let token_cloned = token.clone();
+        match bc!(self, token_cloned, dry_run_transaction, tx_cloned) {
+            Err(err) if err.is_method_not_found() => {
+                log::debug!("dry_run_transaction is not found, fallback to estimate_cycles");
+                self.estimate_cycles_cancellable(tx, token)
+            }
+            result => result,
+        }
+    }
+
+    // Calls `dry_run_transaction` without the warning and the fallback, for the old nodes.
+    pub(super) fn dry_run_transaction_legacy(
+        &self,
+        tx: packed::Transaction,
+    ) -> Result<rpc::DryRunResult> {
         b!(self, dry_run_transaction, tx)
     }
 
+    pub fn estimate_cycles(&self, tx: packed::Transaction) -> Result<rpc::DryRunResult> {
+        b!(self, estimate_cycles, tx)
+    }
+
+    pub fn estimate_cycles_cancellable(
+        &self,
+        tx: packed::Transaction,
+        token: CancellationToken,
+    ) -> Result<rpc::DryRunResult> {
+        bc!(self, token, estimate_cycles, tx)
+    }
+
     pub fn calculate_dao_maximum_withdraw(
         &self,
         out_point: packed::OutPoint,
@@ -371,6 +490,26 @@ impl HttpClient {
         )
     }
 
+    fn get_packed_block(
+        &self,
+        block_hash: fixed::H256,
+    ) -> impl Future<Item = Option<packed::Block>, Error = Error> {
+        c!(self, get_packed_block, block_hash, Some(0.into())).and_then(decode_block)
+    }
+
+    fn get_packed_block_by_number(
+        &self,
+        block_number: core::BlockNumber,
+    ) -> impl Future<Item = Option<packed::Block>, Error = Error> {
+        c!(
+            self,
+            get_packed_block_by_number,
+            block_number.into(),
+            Some(0.into())
+        )
+        .and_then(decode_block)
+    }
+
     fn get_header(
         &self,
         block_hash: fixed::H256,
@@ -392,7 +531,7 @@ impl HttpClient {
         )
     }
 
-    fn get_transaction(
+    pub(super) fn get_transaction(
         &self,
         tx_hash: fixed::H256,
     ) -> impl Future<Item = Option<rpc::TransactionWithStatus>, Error = Error> {
@@ -413,7 +552,7 @@ impl HttpClient {
         ci!(self, get_tip_header, verbosity.map(Into::into))
     }
 
-    fn get_live_cell(
+    pub(super) fn get_live_cell(
         &self,
         out_point: packed::OutPoint,
         with_data: bool,
@@ -590,6 +729,13 @@ impl HttpClient {
         c!(self, dry_run_transaction, tx.into())
     }
 
+    fn estimate_cycles(
+        &self,
+        tx: packed::Transaction,
+    ) -> impl Future<Item = rpc::DryRunResult, Error = Error> {
+        c!(self, estimate_cycles, tx.into())
+    }
+
     fn calculate_dao_maximum_withdraw(
         &self,
         out_point: packed::OutPoint,
@@ -623,7 +769,7 @@ impl HttpClient {
         name: String,
         config_opt: Option<rpc::ExtraLoggerConfig>,
     ) -> impl Future<Item = (), Error = Error> {
-        c!(self, set_extra_logger, name, config_opt.map(Into::into))
+        c!(self, set_extra_logger, name, config_opt)
     }
 
     //
@@ -668,3 +814,19 @@ impl HttpClient {
         )
     }
 }
+
+// decode the molecule bytes which are returned with verbosity 0
+fn decode_block(bytes: Option<rpc::JsonBytes>) -> Result<Option<packed::Block>> {
+    bytes
+        .map(|bytes| {
+            packed::Block::from_slice(bytes.as_bytes())
+                .map_err(|err| Error::parse(format!("invalid molecule block: {}", err)))
+        })
+        .transpose()
+}
+
+fn warn_dry_run_transaction_deprecated() {
+    DRY_RUN_TRANSACTION_DEPRECATED.call_once(|| {
+        log::warn!("dry_run_transaction is deprecated, use estimate_cycles or cycles_for instead");
+    });
+}
@@ -6,27 +6,96 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use jsonrpc_core_client::transports::http;
+use jsonrpc_core::{self as rpc, futures::Future};
 use uckb_jsonrpc_core::client::HttpClient as RawHttpClient;
 use url::Url;
 
-use crate::{error::Result, runtime::Runtime};
+use crate::{
+    error::{Error, Result},
+    middleware::Middlewares,
+    runtime::Runtime,
+};
 
+mod helpers;
 mod methods;
+mod transport;
 
+use self::transport::{Failure, Transport};
+
+pub(super) use self::transport::SingleFlight;
+
+pub use helpers::{
+    BlockId, EpochDaoInfo, ForkDiff, GenesisInfo, NetworkType, NodeStatus, OutPointStatus,
+    PeerSummary, PeerSyncState, Witness,
+};
+
+#[derive(Clone)]
 pub(super) struct HttpClient {
-    client: RawHttpClient,
+    rt: Runtime,
+    transport: Transport,
+    middlewares: Middlewares,
+    single_flight: SingleFlight,
+    url: Url,
 }
 
 impl HttpClient {
-    pub(super) fn new(rt: Runtime, url: &Url) -> Result<Self> {
+    pub(super) fn new(
+        rt: Runtime,
+        url: &Url,
+        user_agent: &str,
+        middlewares: Middlewares,
+        single_flight: SingleFlight,
+    ) -> Result<Self> {
         log::trace!("initialize a http client to connect {}", url);
-        let fut_conn = http::connect::<RawHttpClient>(url.as_str());
-        let client = rt.block_on_01(fut_conn)?;
-        Ok(Self { client })
+        let transport = Transport::new(url, user_agent)?;
+        let url = url.to_owned();
+        Ok(Self {
+            rt,
+            transport,
+            middlewares,
+            single_flight,
+            url,
+        })
+    }
+
+    // Creates a http client which shares the connections with this one, but applies
+    // its own middlewares and shares its own identical calls.
+    pub(super) fn fork(
+        &self,
+        rt: Runtime,
+        middlewares: Middlewares,
+        single_flight: SingleFlight,
+    ) -> Self {
+        log::trace!("fork a http client to connect {}", self.url);
+        Self {
+            rt,
+            transport: self.transport.clone(),
+            middlewares,
+            single_flight,
+            url: self.url.clone(),
+        }
+    }
+
+    // Opens a channel for one call, the failure of the transport is reported by the returned
+    // `Failure` rather than by the response.
+    fn client(&self) -> (RawHttpClient, Failure) {
+        let (channel, failure) = self.transport.connect(
+            &self.rt,
+            self.middlewares.clone(),
+            self.single_flight.clone(),
+        );
+        (RawHttpClient::from(channel), failure)
+    }
+
+    // Sends the calls in one JSON-RPC batch, the results are in the same order as the calls.
+    fn batch(
+        &self,
+        calls: Vec<(String, rpc::Params)>,
+    ) -> impl Future<Item = Vec<rpc::Result<rpc::Value>>, Error = Error> {
+        self.transport.batch(self.middlewares.clone(), calls)
     }
 
-    pub(super) fn client(&self) -> RawHttpClient {
-        self.client.clone()
+    pub(super) fn url(&self) -> &Url {
+        &self.url
     }
 }
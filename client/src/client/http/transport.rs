@@ -0,0 +1,604 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{
+    collections::HashMap,
+    io, result,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use hyper::{
+    client::HttpConnector,
+    header::{self, HeaderValue},
+    Body, Client as RawHyperClient, Request, Uri,
+};
+use jsonrpc_core::{
+    self as rpc,
+    futures::{
+        future::{self, Either},
+        sync::{mpsc, oneshot},
+        Future, Sink as _, Stream as _,
+    },
+};
+use jsonrpc_core_client::{transports::duplex, RpcChannel, RpcError};
+use parking_lot::Mutex;
+use url::Url;
+
+use crate::{
+    error::{Error, Result},
+    middleware::Middlewares,
+    runtime::Runtime,
+};
+
+// Same as the HTTP transport in `jsonrpc-core-client`.
+const MAX_PARALLEL: usize = 8;
+
+type HyperClient = RawHyperClient<HttpConnector, Body>;
+
+// The transport failures are not JSON-RPC errors, so they are kept apart from the errors
+// which are returned by the node.
+#[derive(Clone)]
+enum CallError {
+    Node(rpc::Error),
+    Transport(String),
+}
+
+type CallResult = result::Result<rpc::Value, CallError>;
+
+// The transport failure of the call on a channel.
+//
+// The duplex only delivers JSON-RPC responses, so the failure is recorded here before a
+// placeholder response is delivered, then the caller takes it out of band.
+#[derive(Clone, Default)]
+pub(super) struct Failure(Arc<Mutex<Option<String>>>);
+
+impl Failure {
+    pub(super) fn into_error(self, error: RpcError) -> Error {
+        match self.0.lock().take() {
+            Some(message) => Error::RpcTransport(message),
+            None => error.into(),
+        }
+    }
+
+    fn set(&self, message: String) {
+        *self.0.lock() = Some(message);
+    }
+}
+
+// Identical method calls which are in flight share one request, they are keyed by the
+// method and the params.
+#[derive(Clone, Default)]
+pub(crate) struct SingleFlight {
+    enabled: Arc<AtomicBool>,
+    waiters: Arc<Mutex<HashMap<String, Vec<oneshot::Sender<CallResult>>>>>,
+}
+
+impl SingleFlight {
+    pub(crate) fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    // Copies whether it's enabled, the calls in flight are not shared with the copy.
+    pub(crate) fn fork(&self) -> Self {
+        let enabled = Arc::new(AtomicBool::new(self.enabled.load(Ordering::Relaxed)));
+        Self {
+            enabled,
+            waiters: Default::default(),
+        }
+    }
+
+    // Returns `None` if there is no identical call in flight, then the caller should send the
+    // request and `finish` it.
+    fn join(&self, key: &str) -> Option<oneshot::Receiver<CallResult>> {
+        let mut waiters = self.waiters.lock();
+        if let Some(list) = waiters.get_mut(key) {
+            let (sender, receiver) = oneshot::channel();
+            list.push(sender);
+            Some(receiver)
+        } else {
+            waiters.insert(key.to_owned(), Vec::new());
+            None
+        }
+    }
+
+    fn finish(&self, key: &str, result: &CallResult) {
+        if let Some(list) = self.waiters.lock().remove(key) {
+            for sender in list {
+                let _result = sender.send(result.clone());
+            }
+        }
+    }
+}
+
+// The HTTP transport in `jsonrpc-core-client` doesn't expose the messages, so a duplex
+// transport is used to apply the middlewares to the raw messages.
+//
+// The hyper client keeps a pool of connections, so all channels which are opened from the
+// same transport share the connections. A channel is opened for each call, so its failure
+// belongs to that call.
+#[derive(Clone)]
+pub(super) struct Transport {
+    client: HyperClient,
+    uri: Uri,
+    user_agent: HeaderValue,
+}
+
+impl Transport {
+    pub(super) fn new(url: &Url, user_agent: &str) -> Result<Self> {
+        let uri = url.as_str().parse::<Uri>().map_err(Error::http_client)?;
+        let user_agent = HeaderValue::from_str(user_agent).map_err(|err| {
+            Error::invalid_argument(format!("invalid user agent {:?}: {}", user_agent, err))
+        })?;
+        let client = HyperClient::new();
+        Ok(Self {
+            client,
+            uri,
+            user_agent,
+        })
+    }
+
+    pub(super) fn connect(
+        &self,
+        rt: &Runtime,
+        middlewares: Middlewares,
+        single_flight: SingleFlight,
+    ) -> (RpcChannel, Failure) {
+        let (request_sender, request_receiver) = mpsc::unbounded::<String>();
+        let (response_sender, response_receiver) = mpsc::unbounded::<String>();
+        let sink = request_sender.sink_map_err(|e| RpcError::Other(e.into()));
+        let stream = response_receiver.map_err(|()| {
+            let e = io::Error::new(io::ErrorKind::BrokenPipe, "http transport is closed");
+            RpcError::Other(e.into())
+        });
+        let (rpc_client, sender) = duplex(sink, stream);
+        let transport = self.clone();
+        let failure = Failure::default();
+        let failure_cloned = failure.clone();
+        let worker = request_receiver
+            .map(move |request| {
+                send(
+                    &transport,
+                    &middlewares,
+                    &single_flight,
+                    &failure_cloned,
+                    request,
+                )
+            })
+            .buffer_unordered(MAX_PARALLEL)
+            .filter_map(|response| response)
+            .forward(response_sender.sink_map_err(|_| ()))
+            .map(|_| ());
+        // One task for each call, it ends once the call is done and the channel is dropped.
+        rt.spawn_01(rpc_client.map_err(|_| ()).join(worker).map(|_| ()));
+        (sender, failure)
+    }
+
+    // Sends the calls in one batch request, the results are in the same order as the calls.
+    //
+    // The node may reorder the responses of a batch, so they are matched by the ids, which are
+    // the indexes of the calls. The identical calls in a batch are not shared.
+    pub(super) fn batch(
+        &self,
+        middlewares: Middlewares,
+        calls: Vec<(String, rpc::Params)>,
+    ) -> impl Future<Item = Vec<rpc::Result<rpc::Value>>, Error = Error> {
+        if calls.is_empty() {
+            // an empty batch is an invalid request
+            return Either::A(future::ok(Vec::new()));
+        }
+        let mut methods = Vec::with_capacity(calls.len());
+        let calls = calls
+            .into_iter()
+            .enumerate()
+            .map(|(index, (method, mut params))| {
+                middlewares.before(&method, &mut params);
+                methods.push(method.clone());
+                rpc::Call::MethodCall(rpc::MethodCall {
+                    jsonrpc: Some(rpc::Version::V2),
+                    method,
+                    params,
+                    id: rpc::Id::Num(index as u64),
+                })
+            })
+            .collect();
+        let request = rpc::to_string(&rpc::Request::Batch(calls)).expect("serialize a batch");
+        let fut = self
+            .post(request)
+            .map_err(|message| {
+                log::warn!("http transport failed to send a batch: {}", message);
+                Error::RpcTransport(message)
+            })
+            .and_then(move |body| receive_batch(&middlewares, &methods, &body));
+        Either::B(fut)
+    }
+
+    // Posts a JSON-RPC message, and returns the body of the response.
+    fn post(&self, body: String) -> impl Future<Item = hyper::Chunk, Error = String> {
+        let http_request = Request::post(&self.uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::ACCEPT, "application/json")
+            .header(header::USER_AGENT, self.user_agent.clone())
+            .body(body.into())
+            .expect("uri and request headers are valid");
+        self.client
+            .request(http_request)
+            .map_err(|e| e.to_string())
+            .and_then(|response| {
+                if response.status().is_success() {
+                    Either::A(response.into_body().concat2().map_err(|e| e.to_string()))
+                } else {
+                    let message = format!("unexpected response status code: {}", response.status());
+                    Either::B(future::err(message))
+                }
+            })
+    }
+}
+
+fn send(
+    transport: &Transport,
+    middlewares: &Middlewares,
+    single_flight: &SingleFlight,
+    failure: &Failure,
+    request: String,
+) -> impl Future<Item = Option<String>, Error = ()> {
+    let call = match rpc::serde_from_str(&request) {
+        Ok(rpc::Call::MethodCall(mut call)) => {
+            middlewares.before(&call.method, &mut call.params);
+            Some(call)
+        }
+        _ => None,
+    };
+    let key = match call {
+        Some(ref call) if single_flight.enabled.load(Ordering::Relaxed) => {
+            let params = rpc::to_string(&call.params).expect("serialize params");
+            let key = format!("{}:{}", call.method, params);
+            if let Some(receiver) = single_flight.join(&key) {
+                log::trace!("http transport shares an identical call of {}", call.method);
+                let (id, jsonrpc) = (call.id.clone(), call.jsonrpc);
+                let failure = failure.clone();
+                let fut = receiver.then(move |result| {
+                    let result = result.unwrap_or_else(|_| {
+                        Err(CallError::Transport(
+                            "the shared call is cancelled".to_owned(),
+                        ))
+                    });
+                    Ok(Some(respond(&failure, result, id, jsonrpc)))
+                });
+                return Either::A(fut);
+            }
+            Some(key)
+        }
+        _ => None,
+    };
+    let (request, context) = match call {
+        Some(call) => {
+            let request = rpc::to_string(&call).expect("serialize a method call");
+            (request, Some((call.method, call.id, call.jsonrpc)))
+        }
+        None => (request, None),
+    };
+    let middlewares = middlewares.clone();
+    let single_flight = single_flight.clone();
+    let failure = failure.clone();
+    let fut = transport.post(request).then(move |result| {
+        let response = context.map(|(method, id, jsonrpc)| {
+            let result = receive(&middlewares, &method, result);
+            if let Some(ref key) = key {
+                single_flight.finish(key, &result);
+            }
+            respond(&failure, result, id, jsonrpc)
+        });
+        Ok(response)
+    });
+    Either::B(fut)
+}
+
+fn receive(
+    middlewares: &Middlewares,
+    method: &str,
+    result: result::Result<hyper::Chunk, String>,
+) -> CallResult {
+    let output = result.and_then(|body| {
+        rpc::serde_from_str::<rpc::Output>(&String::from_utf8_lossy(&body))
+            .map_err(|e| format!("failed to parse the response: {}", e))
+    });
+    match output {
+        Ok(output) => {
+            let mut result = output.into();
+            middlewares.after(method, &mut result);
+            result.map_err(CallError::Node)
+        }
+        Err(message) => {
+            log::warn!("http transport failed to call {}: {}", method, message);
+            Err(CallError::Transport(message))
+        }
+    }
+}
+
+fn receive_batch(
+    middlewares: &Middlewares,
+    methods: &[String],
+    body: &[u8],
+) -> Result<Vec<rpc::Result<rpc::Value>>> {
+    let response = rpc::serde_from_str::<rpc::Response>(&String::from_utf8_lossy(body))
+        .map_err(|e| Error::RpcTransport(format!("failed to parse the response: {}", e)))?;
+    let outputs = match response {
+        rpc::Response::Batch(outputs) => outputs,
+        // the node rejects the whole batch
+        rpc::Response::Single(output) => {
+            let message = "a single response is returned for a batch";
+            return Err(rpc::Result::from(output)
+                .err()
+                .map(Error::RpcError)
+                .unwrap_or_else(|| Error::RpcTransport(message.to_owned())));
+        }
+    };
+    let mut outputs = outputs
+        .into_iter()
+        .filter_map(|output| match *output.id() {
+            rpc::Id::Num(index) => Some((index, output)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    methods
+        .iter()
+        .enumerate()
+        .map(|(index, method)| {
+            let output = outputs.remove(&(index as u64)).ok_or_else(|| {
+                Error::RpcTransport(format!("no response for the call #{} of a batch", index))
+            })?;
+            let mut result = output.into();
+            middlewares.after(method, &mut result);
+            Ok(result)
+        })
+        .collect()
+}
+
+fn respond(
+    failure: &Failure,
+    result: CallResult,
+    id: rpc::Id,
+    jsonrpc: Option<rpc::Version>,
+) -> String {
+    let result = result.map_err(|err| match err {
+        CallError::Node(inner) => inner,
+        CallError::Transport(message) => {
+            failure.set(message.clone());
+            rpc::Error {
+                code: rpc::ErrorCode::InternalError,
+                message,
+                data: None,
+            }
+        }
+    });
+    let output = rpc::Output::from(result, id, jsonrpc);
+    rpc::to_string(&output).expect("serialize a response")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        result,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use jsonrpc_core as rpc;
+    use parking_lot::Mutex;
+    use uckb_jsonrpc_core::types::fixed::H256;
+
+    use crate::{
+        error::Error,
+        middleware::{Middleware, Params, RpcError, Value},
+        mock::{to_value, MockHttpServer},
+        Client,
+    };
+
+    // Logs what it sees, and the first one rewrites the messages.
+    struct Recorder {
+        name: &'static str,
+        rewrites: bool,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Recorder {
+        fn before(&self, method: &str, params: &mut Params) {
+            let params_str = rpc::to_string(params).unwrap();
+            let entry = format!("{} before {} {}", self.name, method, params_str);
+            self.log.lock().push(entry);
+            if self.rewrites {
+                *params = Params::Array(vec!["0x2".into()]);
+            }
+        }
+
+        fn after(&self, method: &str, result: &mut result::Result<Value, RpcError>) {
+            let result_str = result.as_ref().unwrap().to_string();
+            let entry = format!("{} after {} {}", self.name, method, result_str);
+            self.log.lock().push(entry);
+            if self.rewrites {
+                *result = Ok(to_value(H256([3; 32])));
+            }
+        }
+    }
+
+    #[test]
+    fn middlewares_run_in_order() {
+        // the hash of a block is filled with its number
+        let server = MockHttpServer::start(|_, params| {
+            let (number,) = params.clone().parse::<(String,)>()?;
+            let number = u8::from_str_radix(number.trim_start_matches("0x"), 16).unwrap();
+            Ok(to_value(H256([number; 32])))
+        });
+        let client = server.client();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for (name, rewrites) in &[("first", true), ("second", false)] {
+            client.add_middleware(Arc::new(Recorder {
+                name,
+                rewrites: *rewrites,
+                log: Arc::clone(&log),
+            }));
+        }
+        let hash = client.get_block_hash(1).unwrap();
+        assert_eq!(hash, Some(H256([3; 32])));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].params, Params::Array(vec!["0x2".into()]));
+        let hash_str = |number| to_value(H256([number; 32])).to_string();
+        let expected = vec![
+            "first before get_block_hash [\"0x1\"]".to_owned(),
+            "second before get_block_hash [\"0x2\"]".to_owned(),
+            format!("first after get_block_hash {}", hash_str(2)),
+            format!("second after get_block_hash {}", hash_str(3)),
+        ];
+        assert_eq!(*log.lock(), expected);
+    }
+
+    #[test]
+    fn node_errors_are_not_transport_failures() {
+        let server = MockHttpServer::start(|_, _| {
+            Err(rpc::Error {
+                code: rpc::ErrorCode::ServerError(-32099),
+                message: "returned by the node".to_owned(),
+                data: None,
+            })
+        });
+        let client = server.client();
+        match client.get_tip_block_number() {
+            Err(Error::RpcError(err)) => {
+                assert_eq!(err.code, rpc::ErrorCode::ServerError(-32099));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn unreachable_node_is_a_transport_failure() {
+        let server = MockHttpServer::start(|_, _| Ok(rpc::Value::Null));
+        let client = server.client();
+        drop(server);
+        match client.get_tip_block_number() {
+            Err(Error::RpcTransport(_)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn user_agent_is_sent() {
+        let server = MockHttpServer::start(|_, _| Ok("0x1".into()));
+        server.client().get_tip_block_number().unwrap();
+        let client = Client::builder()
+            .http(server.url())
+            .user_agent("test-agent/1.0".to_owned())
+            .worker_threads(1)
+            .build()
+            .unwrap();
+        client.get_tip_block_number().unwrap();
+        let user_agents = server
+            .requests()
+            .into_iter()
+            .map(|req| req.user_agent)
+            .collect::<Vec<_>>();
+        let default = format!("uckb-jsonrpc-sdk/{}", env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            user_agents,
+            vec![Some(default), Some("test-agent/1.0".to_owned())]
+        );
+    }
+
+    // Makes the identical calls in threads at the same time, the node answers after a while
+    // so the calls are in flight together. Returns the results and the count of the calls
+    // which reach the node.
+    fn call_concurrently(
+        threads: usize,
+        result: rpc::Result<rpc::Value>,
+    ) -> (Vec<crate::error::Result<u64>>, usize) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_cloned = Arc::clone(&hits);
+        let server = MockHttpServer::start(move |_, _| {
+            hits_cloned.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(200));
+            result.clone()
+        });
+        let client = Arc::new(server.client());
+        client.enable_single_flight();
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles = (0..threads)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    client.get_tip_block_number()
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        (results, hits.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn single_flight_shares_identical_calls() {
+        let (results, hits) = call_concurrently(4, Ok("0x10".into()));
+        assert_eq!(hits, 1);
+        for result in results {
+            assert_eq!(result.unwrap(), 16);
+        }
+    }
+
+    #[test]
+    fn single_flight_delivers_the_error_to_all_waiters() {
+        let err = rpc::Error {
+            code: rpc::ErrorCode::ServerError(-32099),
+            message: "shared failure".to_owned(),
+            data: None,
+        };
+        let (results, hits) = call_concurrently(4, Err(err));
+        assert_eq!(hits, 1);
+        for result in results {
+            match result {
+                Err(Error::RpcError(err)) => {
+                    assert_eq!(err.code, rpc::ErrorCode::ServerError(-32099));
+                    assert_eq!(err.message, "shared failure");
+                }
+                result => panic!("unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn single_flight_is_disabled_by_default() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_cloned = Arc::clone(&hits);
+        let server = MockHttpServer::start(move |_, _| {
+            hits_cloned.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            Ok("0x10".into())
+        });
+        let client = Arc::new(server.client());
+        let handles = (0..2)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                thread::spawn(move || client.get_tip_block_number().unwrap())
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 16);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+}
@@ -6,36 +6,141 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use jsonrpc_server_utils::tokio::runtime::Runtime as RawRuntime01;
 use parking_lot::RwLock;
 use tokio::runtime::Runtime as RawRuntime;
+use uckb_jsonrpc_core::types::rpc;
 use url::Url;
 
 use crate::{
     error::{Error, Result},
+    middleware::{Middleware, Middlewares},
     runtime::Runtime,
 };
 
+mod builder;
 mod http;
 mod tcp;
 
-use self::{http::HttpClient, tcp::TcpClient};
+use self::{
+    http::{HttpClient, SingleFlight},
+    tcp::TcpClient,
+};
+
+pub use self::{
+    builder::ClientBuilder,
+    http::{
+        BlockId, EpochDaoInfo, ForkDiff, GenesisInfo, NetworkType, NodeStatus, OutPointStatus,
+        PeerSummary, PeerSyncState, Witness,
+    },
+    tcp::{DropPolicy, SubscribeOptions, SubscriptionHandle, TcpOptions, TopicStream},
+};
+
+const DEFAULT_USER_AGENT: &str = concat!("uckb-jsonrpc-sdk/", env!("CARGO_PKG_VERSION"));
 
+// The local node info is rarely changed, cache it for a while.
+const DEFAULT_NODE_INFO_TTL: Duration = Duration::from_secs(60);
+
+/// A client to a CKB node.
+///
+/// `Client` is `Send + Sync`, all queries take `&self`, so it can be shared between threads
+/// behind an `Arc` without any extra locks. Concurrent queries don't block each other, and
+/// they don't touch the state of the TCP subscriptions.
+///
+/// Each query sends its own request, so the queries from different threads run concurrently
+/// over the same connections, and the order of their responses is not defined.
+///
+/// Enabling a transport requires `&mut self`, so transports should be enabled before the
+/// client is shared.
+///
+/// The queries are blocking. They must not be made from the threads of an async runtime,
+/// such as inside an async task or a direct subscription handler, which could block the
+/// thread that is supposed to drive the request. A query made from a worker of the legacy
+/// runtime returns `Error::Runtime` instead of blocking. The handlers which are subscribed
+/// with `SubscribeOptions` run on their own threads, so they can make queries.
+///
+/// Cloning a client is cheap, the clones share the runtimes and the connections. The TCP
+/// client is shared, so the subscriptions are shared between the clones, and a subscription
+/// can be unsubscribed via any clone. Each clone has its own middlewares, which are copied
+/// from the original when it is cloned, and its own cached states. Enabling a transport on
+/// a clone only changes that clone.
 pub struct Client {
     runtime: Runtime,
-    tcp: Option<TcpClient>,
+    tcp: Option<Arc<TcpClient>>,
     http: Option<HttpClient>,
+    node_info: RwLock<Option<(rpc::LocalNode, Instant)>>,
+    node_info_ttl: RwLock<Duration>,
+    network_type: RwLock<Option<NetworkType>>,
+    genesis_info: RwLock<Option<GenesisInfo>>,
+    middlewares: Middlewares,
+    single_flight: SingleFlight,
+    user_agent: String,
+}
+
+// Keep the guarantee which is documented above.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Client>();
+};
+
+impl Clone for Client {
+    fn clone(&self) -> Self {
+        let middlewares = self.middlewares.fork();
+        let single_flight = self.single_flight.fork();
+        let http = self
+            .http
+            .as_ref()
+            .map(|http| http.fork(self.runtime(), middlewares.clone(), single_flight.clone()));
+        Self {
+            runtime: self.runtime(),
+            tcp: self.tcp.clone(),
+            http,
+            node_info: RwLock::new(self.node_info.read().clone()),
+            node_info_ttl: RwLock::new(*self.node_info_ttl.read()),
+            network_type: RwLock::new(*self.network_type.read()),
+            genesis_info: RwLock::new(self.genesis_info.read().clone()),
+            middlewares,
+            single_flight,
+            user_agent: self.user_agent.clone(),
+        }
+    }
 }
 
 impl Client {
+    /// The preferred way to create a client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Creates a client by a URL, see `ClientBuilder::url_str`.
+    pub fn from_url_str(input: &str) -> Result<Self> {
+        Self::builder().url_str(input)?.build()
+    }
+
+    /// Creates a client by the environment variables, see `ClientBuilder::env`.
+    pub fn from_env() -> Result<Self> {
+        Self::builder().env()?.build()
+    }
+
     pub fn new(rt: Arc<RawRuntime>, rt01: Arc<RwLock<RawRuntime01>>) -> Self {
         log::info!("create a new client");
         Self {
             runtime: Runtime::new(rt, rt01),
             tcp: None,
             http: None,
+            node_info: RwLock::new(None),
+            node_info_ttl: RwLock::new(DEFAULT_NODE_INFO_TTL),
+            network_type: RwLock::new(None),
+            genesis_info: RwLock::new(None),
+            middlewares: Middlewares::default(),
+            single_flight: SingleFlight::default(),
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
         }
     }
 
@@ -44,26 +149,168 @@ impl Client {
     }
 
     fn tcp(&self) -> Result<&TcpClient> {
-        self.tcp.as_ref().ok_or(Error::NoTcpClient)
+        self.tcp.as_deref().ok_or(Error::NoTcpClient)
     }
 
     fn http(&self) -> Result<&HttpClient> {
         self.http.as_ref().ok_or(Error::NoHttpClient)
     }
 
-    pub fn enable_tcp(&mut self, addr: &SocketAddr) -> Result<&mut Self> {
+    /// Connects to a node via TCP, replaces the previous TCP client if the address is changed,
+    /// and the subscriptions of the previous TCP client are closed once no clones of this
+    /// client use it.
+    ///
+    /// Returns `false` if it's already connected to the address.
+    ///
+    /// Breaking change since 0.3.1: it returned `Result<&mut Self>` before, so the calls
+    /// can't be chained anymore.
+    pub fn enable_tcp(&mut self, addr: &SocketAddr) -> Result<bool> {
+        self.enable_tcp_with_options(addr, TcpOptions::default())
+    }
+
+    /// Same as `enable_tcp`, but with the options of the TCP socket.
+    ///
+    /// Returns `false` if it's already connected to the address with the same options.
+    ///
+    /// Breaking change since 0.3.1: it returned `Result<&mut Self>` before.
+    pub fn enable_tcp_with_options(
+        &mut self,
+        addr: &SocketAddr,
+        options: TcpOptions,
+    ) -> Result<bool> {
         log::info!("enable tcp client");
-        if self.tcp.is_none() {
-            self.tcp = Some(TcpClient::new(self.runtime(), addr)?);
+        if let Some(ref tcp) = self.tcp {
+            if tcp.addr() == addr && tcp.options() == &options {
+                return Ok(false);
+            }
+            log::info!("replace tcp client which connects {}", tcp.addr());
         }
-        Ok(self)
+        self.tcp = Some(Arc::new(TcpClient::new(self.runtime(), addr, options)?));
+        Ok(true)
     }
 
-    pub fn enable_http(&mut self, url: &Url) -> Result<&mut Self> {
+    /// Connects to a node via HTTP, replaces the previous HTTP client if the URL is changed,
+    /// and the cached states of the previous node are cleared.
+    ///
+    /// Returns `false` if it's already connected to the URL.
+    ///
+    /// Breaking change since 0.3.1: it returned `Result<&mut Self>` before, so the calls
+    /// can't be chained anymore.
+    pub fn enable_http(&mut self, url: &Url) -> Result<bool> {
         log::info!("enable http client");
-        if self.http.is_none() {
-            self.http = Some(HttpClient::new(self.runtime(), url)?);
+        if let Some(ref http) = self.http {
+            if http.url() == url {
+                return Ok(false);
+            }
+            log::info!("replace http client which connects {}", http.url());
         }
-        Ok(self)
+        self.http = Some(HttpClient::new(
+            self.runtime(),
+            url,
+            &self.user_agent,
+            self.middlewares.clone(),
+            self.single_flight.clone(),
+        )?);
+        *self.node_info.get_mut() = None;
+        *self.network_type.get_mut() = None;
+        *self.genesis_info.get_mut() = None;
+        Ok(true)
+    }
+
+    /// Sets the `User-Agent` header of the HTTP requests, the default is
+    /// "uckb-jsonrpc-sdk/<version>".
+    ///
+    /// It's applied when the HTTP client is created by `enable_http`. The TCP client has no
+    /// headers, so it's not affected.
+    pub fn set_user_agent(&mut self, user_agent: String) {
+        self.user_agent = user_agent;
+    }
+
+    /// Adds a middleware to intercept the requests and the responses of the HTTP client.
+    ///
+    /// Middlewares are invoked in the order in which they were added. The middleware isn't
+    /// added to the existing clones of this client.
+    pub fn add_middleware(&self, middleware: Arc<dyn Middleware>) {
+        log::info!("add a middleware");
+        self.middlewares.add(middleware);
+    }
+
+    /// Shares one request between the identical calls in flight, all callers receive the
+    /// same result, including the error.
+    ///
+    /// Calls are identical if they have the same method and the same params after the
+    /// middlewares. It isn't enabled for the existing clones of this client.
+    pub fn enable_single_flight(&self) {
+        log::info!("enable single flight");
+        self.single_flight.enable();
+    }
+
+    /// Sets how long the cached local node info is used before it is fetched again.
+    ///
+    /// The cached local node info is used to check the version of the node.
+    pub fn set_node_info_ttl(&self, ttl: Duration) {
+        *self.node_info_ttl.write() = ttl;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use futures::executor::block_on_stream;
+
+    use super::*;
+    use crate::mock::{to_value, MockHttpServer, MockTcpServer};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn chain_node(chain: &'static str) -> MockHttpServer {
+        MockHttpServer::start(move |_, _| {
+            Ok(to_value(rpc::ChainInfo {
+                chain: chain.to_owned(),
+                median_time: 0.into(),
+                epoch: 0.into(),
+                difficulty: Default::default(),
+                is_initial_block_download: false,
+                alerts: Vec::new(),
+            }))
+        })
+    }
+
+    #[test]
+    fn enable_http_reconnects_only_to_a_different_url() {
+        let testnet = chain_node("ckb_testnet");
+        let mainnet = chain_node("ckb");
+        let mut client = testnet.client();
+        assert_eq!(client.network_type().unwrap(), NetworkType::Testnet);
+        assert!(!client.enable_http(&testnet.url()).unwrap());
+        assert_eq!(client.network_type().unwrap(), NetworkType::Testnet);
+        assert_eq!(testnet.methods().len(), 1);
+        // the cached network type of the previous node is cleared
+        assert!(client.enable_http(&mainnet.url()).unwrap());
+        assert_eq!(client.network_type().unwrap(), NetworkType::Mainnet);
+        assert_eq!(testnet.methods().len(), 1);
+        assert_eq!(mainnet.methods().len(), 1);
+    }
+
+    #[test]
+    fn enable_tcp_drains_the_subscriptions_of_the_previous_node() {
+        let first = MockTcpServer::start();
+        let second = MockTcpServer::start();
+        let mut client = first.client();
+        let stream = client.new_tip_header_stream().unwrap();
+        first.wait_for_subscriptions(1);
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _result = sender.send(block_on_stream(stream).next().is_none());
+        });
+        assert!(!client.enable_tcp(&first.addr()).unwrap());
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        assert!(client.enable_tcp(&second.addr()).unwrap());
+        // the stream ends without an error
+        assert!(receiver.recv_timeout(TIMEOUT).unwrap());
+        client.new_tip_header_stream().unwrap();
+        second.wait_for_subscriptions(1);
+        assert_eq!(first.methods(), vec!["subscribe".to_owned()]);
     }
 }
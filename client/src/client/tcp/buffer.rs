@@ -0,0 +1,144 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use super::{DropPolicy, SubscribeOptions};
+
+// A bounded queue between the subscription loop and the thread of a handler.
+//
+// The subscription loop is the only writer and the handler thread is the only reader, so
+// one waiter on each side at most.
+pub(super) struct Buffer {
+    queue: Mutex<VecDeque<String>>,
+    options: SubscribeOptions,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    readable: Notify,
+    writable: Notify,
+}
+
+impl Buffer {
+    pub(super) fn new(options: SubscribeOptions) -> Self {
+        Self {
+            queue: Mutex::new(VecDeque::with_capacity(options.buffer)),
+            options,
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            readable: Notify::new(),
+            writable: Notify::new(),
+        }
+    }
+
+    pub(super) fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // Returns `false` if the buffer is full and the message should wait for room.
+    pub(super) fn try_push(&self, msg: &str) -> bool {
+        let mut queue = self.queue.lock();
+        if queue.len() < self.options.buffer {
+            queue.push_back(msg.to_owned());
+        } else {
+            match self.options.on_full {
+                DropPolicy::DropNewest => {}
+                DropPolicy::DropOldest => {
+                    queue.pop_front();
+                    queue.push_back(msg.to_owned());
+                }
+                DropPolicy::Block => return false,
+            }
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        drop(queue);
+        self.readable.notify_one();
+        true
+    }
+
+    // Waits for room, the message is discarded if the buffer is closed.
+    pub(super) async fn push(&self, msg: &str) {
+        while !self.closed.load(Ordering::Acquire) {
+            if self.try_push(msg) {
+                return;
+            }
+            self.writable.notified().await;
+        }
+    }
+
+    // Returns `None` after the buffer is closed and all messages are taken.
+    pub(super) async fn pop(&self) -> Option<String> {
+        loop {
+            if let Some(msg) = self.queue.lock().pop_front() {
+                self.writable.notify_one();
+                return Some(msg);
+            }
+            if self.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    pub(super) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.readable.notify_one();
+        self.writable.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn drops_the_oldest_by_default() {
+        let options = SubscribeOptions {
+            buffer: 2,
+            ..Default::default()
+        };
+        assert_eq!(options.on_full, DropPolicy::DropOldest);
+        let buffer = Buffer::new(options);
+        for msg in &["a", "b", "c"] {
+            assert!(buffer.try_push(msg));
+        }
+        assert_eq!(buffer.dropped_count(), 1);
+        buffer.close();
+        assert_eq!(block_on(buffer.pop()), Some("b".to_owned()));
+        assert_eq!(block_on(buffer.pop()), Some("c".to_owned()));
+        assert_eq!(block_on(buffer.pop()), None);
+    }
+
+    #[test]
+    fn blocks_only_when_asked() {
+        let options = SubscribeOptions {
+            buffer: 1,
+            on_full: DropPolicy::Block,
+        };
+        let buffer = Buffer::new(options);
+        assert!(buffer.try_push("a"));
+        assert!(!buffer.try_push("b"));
+        assert_eq!(buffer.dropped_count(), 0);
+        let options = SubscribeOptions {
+            buffer: 1,
+            on_full: DropPolicy::DropNewest,
+        };
+        let buffer = Buffer::new(options);
+        assert!(buffer.try_push("a"));
+        assert!(buffer.try_push("b"));
+        assert_eq!(buffer.dropped_count(), 1);
+        assert_eq!(block_on(buffer.pop()), Some("a".to_owned()));
+    }
+}
@@ -6,14 +6,18 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::result;
+use std::{collections::HashMap, result, sync::Arc};
 
-use futures::{compat::Stream01CompatExt as _, StreamExt as _};
-use jsonrpc_server_utils::tokio::prelude::IntoFuture as _;
+use futures::{channel::mpsc as channel, compat::Stream01CompatExt as _, executor, StreamExt as _};
+use jsonrpc_server_utils::tokio::prelude::{Future as _, IntoFuture as _};
+use parking_lot::Mutex;
 use tokio::sync::mpsc;
-use uckb_jsonrpc_core::types::rpc;
+use uckb_jsonrpc_core::types::{core, rpc};
 
-use super::TcpClient;
+use super::{
+    stream, Buffer, Handler, Handlers, Session, SubscribeOptions, Subscriber, SubscriptionHandle,
+    TcpClient, TopicStream,
+};
 use crate::{
     error::{Error, Result},
     runtime::Runtime,
@@ -21,94 +25,392 @@ use crate::{
 };
 
 impl Client {
-    pub fn subscribe_new_tip_header<F>(&self, func: F) -> Result<()>
+    pub fn subscribe_new_tip_header<F>(&self, func: F) -> Result<SubscriptionHandle>
     where
         F: Fn(&str) -> result::Result<(), ()> + 'static + Send,
     {
         self.subscribe(rpc::Topic::NewTipHeader, func)
     }
 
-    pub fn subscribe_new_tip_block<F>(&self, func: F) -> Result<()>
+    pub fn subscribe_new_tip_block<F>(&self, func: F) -> Result<SubscriptionHandle>
     where
         F: Fn(&str) -> result::Result<(), ()> + 'static + Send,
     {
         self.subscribe(rpc::Topic::NewTipBlock, func)
     }
 
-    pub fn subscribe_new_transaction<F>(&self, func: F) -> Result<()>
+    pub fn subscribe_new_transaction<F>(&self, func: F) -> Result<SubscriptionHandle>
     where
         F: Fn(&str) -> result::Result<(), ()> + 'static + Send,
     {
         self.subscribe(rpc::Topic::NewTransaction, func)
     }
 
-    fn subscribe<F>(&self, topic: rpc::Topic, func: F) -> Result<()>
+    /// Adds a handler to a topic.
+    ///
+    /// All handlers of a topic share one subscription to the node, each of them receives
+    /// every message. A handler which returns an error is removed.
+    ///
+    /// Handlers are called one by one in the subscription loop, so a slow handler delays the
+    /// others. They can subscribe or unsubscribe through the client.
+    pub fn subscribe<F>(&self, topic: rpc::Topic, func: F) -> Result<SubscriptionHandle>
     where
         F: Fn(&str) -> result::Result<(), ()> + 'static + Send,
     {
         log::debug!("client subscribe {:?}", topic);
-        self.tcp()?.subscribe(self.runtime(), topic, func)
+        let rt = self.runtime();
+        self.tcp()?
+            .subscribe(self.runtime(), topic, move |handlers, id| {
+                handler_subscriber(&rt, handlers, id, Box::new(func), None)
+            })
     }
-}
 
-impl TcpClient {
-    pub(super) fn subscribe<F>(&self, rt: Runtime, topic: rpc::Topic, func: F) -> Result<()>
+    /// Adds a handler to a topic, the messages are buffered and the handler is called in
+    /// its own thread.
+    ///
+    /// The subscription doesn't wait for the handler until its buffer is full, then a
+    /// message is dropped or waits, according to `options.on_full`. The handler can
+    /// subscribe or unsubscribe through the client.
+    ///
+    /// The buffered messages are still handled after the handler is removed.
+    pub fn subscribe_with_options<F>(
+        &self,
+        topic: rpc::Topic,
+        options: SubscribeOptions,
+        func: F,
+    ) -> Result<SubscriptionHandle>
     where
         F: Fn(&str) -> result::Result<(), ()> + 'static + Send,
     {
-        // remove the previous subscription
-        if let Some(sender) = self.sess.write().remove(&topic) {
-            log::warn!("tcp subscribe {:?} already existed, replace it", topic);
-            rt.block_on(sender.send(())).map_err(|_| {
-                log::error!(
-                    "tcp subscribe {:?} failed to remove the previous subscription",
-                    topic,
-                );
-                Error::tcp_client("failed to remove the previous subscription")
-            })?;
+        log::debug!("client subscribe {:?} with {:?}", topic, options);
+        if options.buffer == 0 {
+            return Err(Error::invalid_argument(
+                "the buffer of a subscription should be greater than 0",
+            ));
         }
-        // setup a new subscription
+        let rt = self.runtime();
+        self.tcp()?
+            .subscribe(self.runtime(), topic, move |handlers, id| {
+                handler_subscriber(&rt, handlers, id, Box::new(func), Some(options))
+            })
+    }
+
+    pub fn new_tip_header_stream(&self) -> Result<TopicStream<core::HeaderView>> {
+        self.topic_stream(rpc::Topic::NewTipHeader, stream::parse_header)
+    }
+
+    pub fn new_tip_block_stream(&self) -> Result<TopicStream<core::BlockView>> {
+        self.topic_stream(rpc::Topic::NewTipBlock, stream::parse_block)
+    }
+
+    /// The items are the transactions with their cycles, sizes and fees.
+    pub fn new_transaction_stream(&self) -> Result<TopicStream<rpc::PoolTransactionEntry>> {
+        self.topic_stream(rpc::Topic::NewTransaction, stream::parse_transaction)
+    }
+
+    fn topic_stream<T>(
+        &self,
+        topic: rpc::Topic,
+        parse: fn(&str) -> Result<T>,
+    ) -> Result<TopicStream<T>> {
+        log::debug!("client subscribe {:?} as a stream", topic);
+        let (sender, receiver) = channel::unbounded();
+        let handle = self
+            .tcp()?
+            .subscribe(self.runtime(), topic, |_, _| Subscriber::Stream(sender))?;
+        Ok(TopicStream::new(handle, receiver, parse))
+    }
+
+    /// Gets the count of messages which are dropped since the buffer of a handler is full.
+    ///
+    /// Returns `None` if the handler was already removed. It's always 0 for the handlers
+    /// which are not buffered.
+    pub fn dropped_count(&self, handle: SubscriptionHandle) -> Result<Option<u64>> {
+        self.tcp().map(|tcp| tcp.dropped_count(handle))
+    }
+
+    /// Removes a handler, the subscription to the node is closed after its last handler
+    /// is removed.
+    ///
+    /// Returns `false` if the handler was already removed.
+    pub fn unsubscribe(&self, handle: SubscriptionHandle) -> Result<bool> {
+        log::debug!("client unsubscribe {:?}", handle.topic());
+        self.tcp().map(|tcp| tcp.unsubscribe(handle))
+    }
+}
+
+impl TcpClient {
+    pub(super) fn subscribe<F>(
+        &self,
+        rt: Runtime,
+        topic: rpc::Topic,
+        new_subscriber: F,
+    ) -> Result<SubscriptionHandle>
+    where
+        F: FnOnce(&Handlers, u64) -> Subscriber,
+    {
+        let handle = SubscriptionHandle {
+            topic,
+            id: self.next_handler_id(),
+        };
+        // share the existed subscription
+        let new_subscriber = match share(&self.sess.read(), topic, handle.id, new_subscriber) {
+            Ok(()) => return Ok(handle),
+            Err(new_subscriber) => new_subscriber,
+        };
+        // setup a new subscription, without the lock since it waits for the node
         log::trace!("tcp subscribe {:?}", topic);
-        let fut_subscribe = self.client().subscribe(topic).into_future();
-        let stream_01 = rt.block_on_01(fut_subscribe).map_err(move |err| {
-            log::error!("failed to subscribe {:?} since {}", topic, err);
-            Error::tcp_client(err)
-        })?;
+        let fut_subscribe = self
+            .client()
+            .subscribe(topic)
+            .into_future()
+            .map_err(move |err| {
+                log::error!("failed to subscribe {:?} since {}", topic, err);
+                Error::tcp_client(err)
+            });
+        let stream_01 = rt.block_on_01(fut_subscribe)?;
+        let mut sess = self.sess.write();
+        // the topic may be subscribed in the meantime, then drop the new subscription
+        let new_subscriber = match share(&sess, topic, handle.id, new_subscriber) {
+            Ok(()) => return Ok(handle),
+            Err(new_subscriber) => new_subscriber,
+        };
         log::trace!("tcp subscribe {:?} is ok", topic);
-        let (sender, mut receiver) = mpsc::channel(1);
+        let handlers: Handlers = Arc::new(Mutex::new(Some(HashMap::new())));
+        let subscriber = new_subscriber(&handlers, handle.id);
+        if let Some(ref mut handlers) = *handlers.lock() {
+            handlers.insert(handle.id, subscriber);
+        }
+        let (stopper, mut receiver) = mpsc::channel(1);
         let mut stream = stream_01.compat();
+        let handlers_cloned = Arc::clone(&handlers);
         let fut = async move {
+            let mut failure = None;
             loop {
                 tokio::select! {
                     _ = receiver.recv() => {
                         log::trace!("tcp subscribe {:?} remove", topic);
                         break;
                     }
-                    Some(resp) = stream.next() => {
+                    resp = stream.next() => {
                         match resp {
-                            Ok(msg) => {
+                            Some(Ok(msg)) => {
                                 log::trace!("tcp subscribe {:?} receive {}", topic, msg);
-                                if func(&msg).is_err() {
+                                let (is_empty, blocked) = dispatch(&handlers_cloned, &msg);
+                                if is_empty {
+                                    log::trace!("tcp subscribe {:?} has no handlers", topic);
                                     break;
                                 }
+                                // wait for the full buffers outside the lock
+                                for buffer in blocked {
+                                    buffer.push(&msg).await;
+                                }
                             }
-                            Err(err) => {
+                            Some(Err(err)) => {
                                 log::warn!("tcp subscribe {:?} got an error {}", topic, err);
+                                failure = Some(err.to_string());
+                                break;
+                            }
+                            // the connection is closed, the stopper alone would never end the loop
+                            None => {
+                                log::warn!("tcp subscribe {:?} is broken", topic);
+                                failure = Some("the connection is broken".to_owned());
                                 break;
                             }
                         }
                     },
-                    else => {
-                        log::warn!("tcp subscribe {:?} is broken", topic);
-                        break;
-                    },
+                }
+            }
+            let handlers = handlers_cloned.lock().take();
+            // streams can't tell a broken connection from an unsubscription by themselves
+            if let (Some(handlers), Some(failure)) = (handlers, failure) {
+                for subscriber in handlers.values() {
+                    if let Subscriber::Stream(sender) = subscriber {
+                        let _result = sender.unbounded_send(Err(Error::tcp_client(&failure)));
+                    }
                 }
             }
             drop(stream);
         };
         rt.spawn(fut);
         // save the subscription and return
-        self.sess.write().insert(topic, sender);
-        Ok(())
+        sess.insert(topic, Session { stopper, handlers });
+        Ok(handle)
+    }
+
+    pub(super) fn unsubscribe(&self, handle: SubscriptionHandle) -> bool {
+        let mut sess = self.sess.write();
+        let (removed, is_empty) = if let Some(session) = sess.get(&handle.topic) {
+            match *session.handlers.lock() {
+                Some(ref mut handlers) => {
+                    let removed = handlers.remove(&handle.id).is_some();
+                    (removed, handlers.is_empty())
+                }
+                None => (false, true),
+            }
+        } else {
+            return false;
+        };
+        // the last handler is removed, so close the subscription
+        if is_empty {
+            if let Some(session) = sess.remove(&handle.topic) {
+                log::trace!("tcp unsubscribe {:?}", handle.topic);
+                let _result = session.stopper.try_send(());
+            }
+        }
+        removed
+    }
+
+    pub(super) fn dropped_count(&self, handle: SubscriptionHandle) -> Option<u64> {
+        let sess = self.sess.read();
+        let session = sess.get(&handle.topic)?;
+        let guard = session.handlers.lock();
+        match guard.as_ref()?.get(&handle.id)? {
+            Subscriber::Direct(_) | Subscriber::Stream(_) => Some(0),
+            Subscriber::Buffered(buffer) => Some(buffer.dropped_count()),
+        }
+    }
+}
+
+// Adds a subscriber to the subscription of the topic if it's still open, otherwise gives
+// `new_subscriber` back.
+fn share<F>(
+    sess: &HashMap<rpc::Topic, Session>,
+    topic: rpc::Topic,
+    id: u64,
+    new_subscriber: F,
+) -> result::Result<(), F>
+where
+    F: FnOnce(&Handlers, u64) -> Subscriber,
+{
+    if let Some(session) = sess.get(&topic) {
+        if let Some(ref mut handlers) = *session.handlers.lock() {
+            log::trace!("tcp subscribe {:?} already existed, share it", topic);
+            let subscriber = new_subscriber(&session.handlers, id);
+            handlers.insert(id, subscriber);
+            return Ok(());
+        }
+        log::trace!("tcp subscribe {:?} was closed, renew it", topic);
+    }
+    Err(new_subscriber)
+}
+
+fn handler_subscriber(
+    rt: &Runtime,
+    handlers: &Handlers,
+    id: u64,
+    func: Handler,
+    options: Option<SubscribeOptions>,
+) -> Subscriber {
+    let options = if let Some(options) = options {
+        options
+    } else {
+        return Subscriber::Direct(Arc::new(Mutex::new(func)));
+    };
+    let buffer = Arc::new(Buffer::new(options));
+    let buffer_cloned = Arc::clone(&buffer);
+    let handlers = Arc::clone(handlers);
+    // handlers are blocking functions, so don't run them on the workers of the runtime
+    rt.spawn_blocking(move || {
+        while let Some(msg) = executor::block_on(buffer_cloned.pop()) {
+            if func(&msg).is_err() {
+                // the subscription is closed when the next message comes, if it's the last one
+                if let Some(ref mut handlers) = *handlers.lock() {
+                    handlers.remove(&id);
+                }
+                break;
+            }
+        }
+    });
+    Subscriber::Buffered(buffer)
+}
+
+// Sends a message to all handlers, returns whether no handlers are left, and the buffers
+// which are full and want to wait.
+//
+// The direct handlers are called after the lock is released, so they can subscribe or
+// unsubscribe through the client.
+fn dispatch(handlers: &Handlers, msg: &str) -> (bool, Vec<Arc<Buffer>>) {
+    let mut blocked = Vec::new();
+    let mut direct = Vec::new();
+    match *handlers.lock() {
+        Some(ref mut handlers) => {
+            handlers.retain(|id, subscriber| match subscriber {
+                Subscriber::Direct(func) => {
+                    direct.push((*id, Arc::clone(func)));
+                    true
+                }
+                Subscriber::Stream(sender) => sender.unbounded_send(Ok(msg.to_owned())).is_ok(),
+                Subscriber::Buffered(buffer) => {
+                    if !buffer.try_push(msg) {
+                        blocked.push(Arc::clone(buffer));
+                    }
+                    true
+                }
+            });
+        }
+        None => return (true, blocked),
+    }
+    let failed = direct
+        .into_iter()
+        .filter(|(_, func)| {
+            let func = func.lock();
+            func(msg).is_err()
+        })
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    let is_empty = match *handlers.lock() {
+        Some(ref mut handlers) => {
+            for id in failed {
+                handlers.remove(&id);
+            }
+            handlers.is_empty()
+        }
+        None => true,
+    };
+    (is_empty, blocked)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc as std_mpsc, time::Duration};
+
+    use super::*;
+    use crate::mock::MockTcpServer;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn handler_unsubscribes_itself() {
+        let server = MockTcpServer::start();
+        let client = server.client();
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let first_handle = Arc::new(Mutex::new(None));
+        let handler = {
+            let client = client.clone();
+            let first = Arc::clone(&first);
+            let first_handle = Arc::clone(&first_handle);
+            move |msg: &str| {
+                first.lock().push(msg.to_owned());
+                if let Some(handle) = *first_handle.lock() {
+                    assert!(client.unsubscribe(handle).unwrap());
+                }
+                Ok(())
+            }
+        };
+        let handle = client.subscribe_new_tip_header(handler).unwrap();
+        *first_handle.lock() = Some(handle);
+        let (sender, receiver) = std_mpsc::channel();
+        client
+            .subscribe_new_tip_header(move |msg| sender.send(msg.to_owned()).map_err(|_| ()))
+            .unwrap();
+        server.wait_for_subscriptions(1);
+        for msg in &["a", "b"] {
+            server.publish(msg);
+            assert_eq!(receiver.recv_timeout(TIMEOUT).unwrap(), *msg);
+        }
+        assert_eq!(*first.lock(), vec!["a".to_owned()]);
+        assert!(!client.unsubscribe(handle).unwrap());
+        assert_eq!(server.methods(), vec!["subscribe".to_owned()]);
     }
 }
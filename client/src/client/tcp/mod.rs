@@ -6,7 +6,16 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{collections::HashMap, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    result,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use jsonrpc_core_client::{transports::duplex, RpcError};
 use jsonrpc_server_utils::{
@@ -17,7 +26,7 @@ use jsonrpc_server_utils::{
         prelude::{Future as _, Sink as _, Stream as _},
     },
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use tokio::sync::mpsc;
 use uckb_jsonrpc_core::{client::TcpClient as RawTcpClient, types::rpc};
 
@@ -26,30 +35,140 @@ use crate::{
     runtime::Runtime,
 };
 
+mod buffer;
 mod methods;
+mod stream;
+
+use self::buffer::Buffer;
+
+pub use self::stream::TopicStream;
+
+type Handler = Box<dyn Fn(&str) -> result::Result<(), ()> + Send>;
+
+// `None` means the subscription is closed.
+type Handlers = Arc<Mutex<Option<HashMap<u64, Subscriber>>>>;
+
+// A handler is called in the subscription loop, or in its own thread behind a buffer.
+// A stream receives the messages through a channel.
+//
+// A direct handler is shared, so the loop calls it outside the lock of the handlers.
+enum Subscriber {
+    Direct(Arc<Mutex<Handler>>),
+    Buffered(Arc<Buffer>),
+    Stream(futures::channel::mpsc::UnboundedSender<Result<String>>),
+}
+
+/// Identifies a handler of a subscription, which is used to unsubscribe it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionHandle {
+    topic: rpc::Topic,
+    id: u64,
+}
+
+/// Options of the TCP socket which is connected to a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// Disables the Nagle algorithm, so the small subscription messages are delivered
+    /// without delay. Default is `true`.
+    pub nodelay: bool,
+    /// The idle time before the keepalive probes are sent, `None` disables the keepalive.
+    /// Default is `None`.
+    pub keepalive: Option<Duration>,
+}
+
+/// What to do with a new message when the buffer of a handler is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drops the new message.
+    DropNewest,
+    /// Drops the oldest message in the buffer to make room for the new message.
+    DropOldest,
+    /// Waits until the handler takes a message, the other handlers of the topic wait too.
+    ///
+    /// If the handler is slow for a long time, the messages queue up in the transport, which
+    /// may reorder them. So it's opt-in, only for the handlers which can't miss a message.
+    Block,
+}
+
+/// Options of a handler whose messages are buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscribeOptions {
+    /// The count of messages which can be buffered, it should be greater than 0.
+    /// Default is 1024.
+    pub buffer: usize,
+    /// Default is `DropPolicy::DropOldest`, so a slow handler doesn't stall the other
+    /// handlers of the topic, and it gets the latest messages.
+    pub on_full: DropPolicy,
+}
+
+// All handlers of a topic share one subscription to the node.
+struct Session {
+    stopper: mpsc::Sender<()>,
+    handlers: Handlers,
+}
 
 pub(super) struct TcpClient {
     client: RawTcpClient,
-    sess: RwLock<HashMap<rpc::Topic, mpsc::Sender<()>>>,
+    addr: SocketAddr,
+    options: TcpOptions,
+    sess: RwLock<HashMap<rpc::Topic, Session>>,
+    next_handler_id: AtomicU64,
+}
+
+impl SubscriptionHandle {
+    pub fn topic(&self) -> rpc::Topic {
+        self.topic
+    }
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+        }
+    }
+}
+
+impl Default for SubscribeOptions {
+    fn default() -> Self {
+        Self {
+            buffer: 1024,
+            on_full: DropPolicy::DropOldest,
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        // stop the thread of the handler once the buffered messages are handled
+        if let Subscriber::Buffered(ref buffer) = self {
+            buffer.close();
+        }
+    }
 }
 
 impl Drop for TcpClient {
     fn drop(&mut self) {
-        for (topic, sender) in self.sess.write().drain() {
+        for (topic, session) in self.sess.write().drain() {
             log::trace!("tcp subscribe {:?} drop", topic);
-            let _result = sender.send(());
+            let _result = session.stopper.try_send(());
         }
     }
 }
 
 impl TcpClient {
-    pub(super) fn new(rt: Runtime, addr: &SocketAddr) -> Result<Self> {
+    pub(super) fn new(rt: Runtime, addr: &SocketAddr, options: TcpOptions) -> Result<Self> {
         log::trace!("initialize a tcp client to connect {}", addr);
-        let fut_conn = TcpStream::connect(addr).map(|stream| {
-            log::trace!("successfully connect via {}", stream.local_addr().unwrap());
-            stream
-        });
-        let stream = rt.block_on_01(fut_conn).map_err(Error::tcp_client)?;
+        let fut_conn = TcpStream::connect(addr)
+            .and_then(move |stream| {
+                log::trace!("successfully connect via {}", stream.local_addr().unwrap());
+                stream.set_nodelay(options.nodelay)?;
+                stream.set_keepalive(options.keepalive)?;
+                Ok(stream)
+            })
+            .map_err(Error::tcp_client);
+        let stream = rt.block_on_01(fut_conn)?;
         let (sink, stream) = StreamCodec::stream_incoming().framed(stream).split();
         let sink = sink.sink_map_err(|e| RpcError::Other(e.into()));
         let stream = stream.map_err(|e| RpcError::Other(e.into()));
@@ -57,10 +176,29 @@ impl TcpClient {
         let client = RawTcpClient::from(sender);
         rt.spawn_01(rpc_client.map_err(|_| ()));
         let sess = RwLock::new(HashMap::new());
-        Ok(Self { client, sess })
+        let next_handler_id = AtomicU64::new(0);
+        Ok(Self {
+            client,
+            addr: addr.to_owned(),
+            options,
+            sess,
+            next_handler_id,
+        })
     }
 
     pub(super) fn client(&self) -> RawTcpClient {
         self.client.clone()
     }
+
+    pub(super) fn addr(&self) -> &SocketAddr {
+        &self.addr
+    }
+
+    pub(super) fn options(&self) -> &TcpOptions {
+        &self.options
+    }
+
+    fn next_handler_id(&self) -> u64 {
+        self.next_handler_id.fetch_add(1, Ordering::Relaxed)
+    }
 }
@@ -0,0 +1,75 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{channel::mpsc, Stream, StreamExt as _};
+use uckb_jsonrpc_core::types::{core, rpc};
+
+use super::SubscriptionHandle;
+use crate::error::{Error, Result};
+
+/// The messages of a topic, which are parsed into typed items.
+///
+/// The stream ends after the handle is unsubscribed. If the connection is broken, an error
+/// is the last item. A dropped stream is unsubscribed when the next message comes.
+pub struct TopicStream<T> {
+    handle: SubscriptionHandle,
+    receiver: mpsc::UnboundedReceiver<Result<String>>,
+    parse: fn(&str) -> Result<T>,
+}
+
+impl<T> TopicStream<T> {
+    pub(super) fn new(
+        handle: SubscriptionHandle,
+        receiver: mpsc::UnboundedReceiver<Result<String>>,
+        parse: fn(&str) -> Result<T>,
+    ) -> Self {
+        Self {
+            handle,
+            receiver,
+            parse,
+        }
+    }
+
+    pub fn handle(&self) -> SubscriptionHandle {
+        self.handle
+    }
+}
+
+impl<T> Stream for TopicStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let parse = this.parse;
+        this.receiver
+            .poll_next_unpin(cx)
+            .map(|item| item.map(|result| result.and_then(|msg| parse(&msg))))
+    }
+}
+
+pub(super) fn parse_header(msg: &str) -> Result<core::HeaderView> {
+    jsonrpc_core::serde_from_str::<rpc::HeaderView>(msg)
+        .map(Into::into)
+        .map_err(|err| Error::parse(format!("failed to parse a header: {}", err)))
+}
+
+pub(super) fn parse_block(msg: &str) -> Result<core::BlockView> {
+    jsonrpc_core::serde_from_str::<rpc::BlockView>(msg)
+        .map(Into::into)
+        .map_err(|err| Error::parse(format!("failed to parse a block: {}", err)))
+}
+
+pub(super) fn parse_transaction(msg: &str) -> Result<rpc::PoolTransactionEntry> {
+    jsonrpc_core::serde_from_str(msg)
+        .map_err(|err| Error::parse(format!("failed to parse a transaction: {}", err)))
+}
@@ -0,0 +1,111 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use uckb_jsonrpc_core::types::{bytes::Bytes, core, fixed, packed, prelude::*, rpc};
+
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", faster_hex::hex_string(bytes).expect("hex encode"))
+}
+
+/// Renders the molecule bytes of an entity as a lowercase `0x`-prefixed hex string.
+///
+/// The output is accepted by the `parse_*_hex` functions in the `parse` module.
+pub fn to_hex<T: Entity>(entity: &T) -> String {
+    bytes_to_hex(entity.as_slice())
+}
+
+pub fn h256_to_hex(hash: &fixed::H256) -> String {
+    bytes_to_hex(hash.as_bytes())
+}
+
+// The same format as `parse::parse_out_point` accepts: "0x{tx_hash}-{index}".
+pub fn out_point_to_string(out_point: &packed::OutPoint) -> String {
+    let index: u32 = out_point.index().unpack();
+    format!("{}-{}", to_hex(&out_point.tx_hash()), index)
+}
+
+// The same names as `parse::parse_script_hash_type` accepts.
+pub fn script_hash_type_to_str(hash_type: core::ScriptHashType) -> &'static str {
+    match hash_type {
+        core::ScriptHashType::Data => "data",
+        core::ScriptHashType::Type => "type",
+    }
+}
+
+// The same names as `parse::parse_dep_type` accepts.
+pub fn dep_type_to_str(dep_type: core::DepType) -> &'static str {
+    match dep_type {
+        core::DepType::Code => "code",
+        core::DepType::DepGroup => "dep_group",
+    }
+}
+
+pub fn h256_to_byte32(hash: &fixed::H256) -> packed::Byte32 {
+    hash.pack()
+}
+
+pub fn byte32_to_h256(hash: &packed::Byte32) -> fixed::H256 {
+    hash.unpack()
+}
+
+pub fn json_bytes_to_vec(bytes: &rpc::JsonBytes) -> Vec<u8> {
+    bytes.as_bytes().to_vec()
+}
+
+pub fn vec_to_json_bytes(bytes: &[u8]) -> rpc::JsonBytes {
+    rpc::JsonBytes::from_vec(bytes.to_vec())
+}
+
+// The molecule bytes have a 4-bytes length header, only the raw data is returned.
+pub fn packed_bytes_to_vec(bytes: &packed::Bytes) -> Vec<u8> {
+    bytes.raw_data().to_vec()
+}
+
+pub fn vec_to_packed_bytes(bytes: &[u8]) -> packed::Bytes {
+    Bytes::from(bytes.to_vec()).pack()
+}
+
+#[cfg(test)]
+mod tests {
+    use uckb_jsonrpc_core::types::fixed::{h256, H256};
+
+    use super::*;
+
+    #[test]
+    fn h256_and_byte32_round_trip() {
+        let hash = h256!("0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8");
+        let byte32 = h256_to_byte32(&hash);
+        assert_eq!(byte32.as_slice(), hash.as_bytes());
+        assert_eq!(byte32_to_h256(&byte32), hash);
+        let zero = packed::Byte32::default();
+        assert_eq!(h256_to_byte32(&byte32_to_h256(&zero)), zero);
+    }
+
+    #[test]
+    fn json_bytes_round_trip() {
+        for bytes in &[&b""[..], b"\x00", b"\x01\x02\x03"] {
+            let json_bytes = vec_to_json_bytes(bytes);
+            assert_eq!(json_bytes.as_bytes(), *bytes);
+            assert_eq!(json_bytes_to_vec(&json_bytes), bytes.to_vec());
+        }
+        let json_bytes = vec_to_json_bytes(&[0xab, 0xcd]);
+        let json = jsonrpc_core::serde_json::to_string(&json_bytes).unwrap();
+        assert_eq!(json, "\"0xabcd\"");
+    }
+
+    #[test]
+    fn packed_bytes_round_trip() {
+        for bytes in &[&b""[..], b"\x00", b"\x01\x02\x03"] {
+            let packed_bytes = vec_to_packed_bytes(bytes);
+            assert_eq!(packed_bytes.raw_data().as_ref(), *bytes);
+            // the length header is not a part of the raw data
+            assert_eq!(packed_bytes.as_slice().len(), bytes.len() + 4);
+            assert_eq!(packed_bytes_to_vec(&packed_bytes), bytes.to_vec());
+        }
+    }
+}
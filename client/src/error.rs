@@ -17,6 +17,18 @@ pub enum Error {
     #[error("runtime error: {0}")]
     Runtime(String),
 
+    #[error("the request was cancelled")]
+    Cancelled,
+    #[error("timeout: {0}")]
+    Timeout(String),
+
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("parse error: {0}")]
+    Parse(String),
+    #[error("corruption: {0}")]
+    Corruption(String),
+
     #[error("client error: TCP client is not activated")]
     NoTcpClient,
     #[error("client error: HTTP client is not activated")]
@@ -27,29 +39,74 @@ pub enum Error {
     #[error("http client error: {0}")]
     HttpClient(String),
 
+    // the request is delivered but the node returns an error
     #[error("rpc error: {0}")]
-    RpcError(cli::RpcError),
+    RpcError(rpc::Error),
+    // failed to deliver the request, or failed to parse the response
+    #[error("rpc transport error: {0}")]
+    RpcTransport(String),
 }
 
 pub type Result<T> = result::Result<T, Error>;
 
 impl From<cli::RpcError> for Error {
     fn from(error: cli::RpcError) -> Self {
-        Self::RpcError(error)
+        match error {
+            cli::RpcError::JsonRpcError(inner) => Self::RpcError(inner),
+            cli::RpcError::ParseError(target, inner) => Self::RpcTransport(format!(
+                "failed to parse the response as {}: {}",
+                target, inner
+            )),
+            cli::RpcError::Timeout => Self::RpcTransport("request timed out".to_owned()),
+            cli::RpcError::Other(inner) => Self::RpcTransport(inner.to_string()),
+        }
     }
 }
 
 impl From<rpc::Error> for Error {
     fn from(error: rpc::Error) -> Self {
-        Self::RpcError(error.into())
+        Self::RpcError(error)
     }
 }
 
 impl Error {
+    pub fn is_method_not_found(&self) -> bool {
+        self.rpc_error_code() == Some(&rpc::ErrorCode::MethodNotFound)
+    }
+
+    // The transaction pool rejects a transaction since its fee rate is lower than the
+    // configured minimum fee rate.
+    pub fn is_min_fee_rate_rejection(&self) -> bool {
+        self.rpc_error_code() == Some(&rpc::ErrorCode::ServerError(-1104))
+    }
+
+    fn rpc_error_code(&self) -> Option<&rpc::ErrorCode> {
+        match self {
+            Self::RpcError(err) => Some(&err.code),
+            _ => None,
+        }
+    }
+
     pub fn runtime<T: fmt::Display>(inner: T) -> Self {
         Self::Runtime(inner.to_string())
     }
 
+    pub fn timeout<T: fmt::Display>(inner: T) -> Self {
+        Self::Timeout(inner.to_string())
+    }
+
+    pub fn invalid_argument<T: fmt::Display>(inner: T) -> Self {
+        Self::InvalidArgument(inner.to_string())
+    }
+
+    pub fn parse<T: fmt::Display>(inner: T) -> Self {
+        Self::Parse(inner.to_string())
+    }
+
+    pub fn corruption<T: fmt::Display>(inner: T) -> Self {
+        Self::Corruption(inner.to_string())
+    }
+
     pub fn tcp_client<T: fmt::Display>(inner: T) -> Self {
         Self::TcpClient(inner.to_string())
     }
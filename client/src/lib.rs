@@ -6,12 +6,28 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+pub extern crate tentacle_multiaddr as multiaddr;
 pub extern crate uckb_jsonrpc_core as core;
 pub extern crate url;
 
+pub use tokio_util::sync::CancellationToken;
+pub use uckb_jsonrpc_core::types::rpc::OutputsValidator;
+
+pub mod convert;
 pub mod error;
+pub mod logger;
+pub mod middleware;
+pub mod parse;
+pub mod system;
 
 mod client;
 mod runtime;
 
-pub use client::Client;
+#[cfg(test)]
+mod mock;
+
+pub use client::{
+    BlockId, Client, ClientBuilder, DropPolicy, EpochDaoInfo, ForkDiff, GenesisInfo, NetworkType,
+    NodeStatus, OutPointStatus, PeerSummary, PeerSyncState, SubscribeOptions, SubscriptionHandle,
+    TcpOptions, TopicStream, Witness,
+};
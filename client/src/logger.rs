@@ -0,0 +1,110 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use uckb_jsonrpc_core::types::rpc;
+
+use crate::error::{Error, Result};
+
+/// Builds the configs for `update_main_logger` and `set_extra_logger`.
+///
+/// For the main logger, the options which are not set keep unchanged in the node.
+#[derive(Debug, Clone, Default)]
+pub struct LoggerConfigBuilder {
+    filter: Option<String>,
+    to_stdout: Option<bool>,
+    to_file: Option<bool>,
+    color: Option<bool>,
+}
+
+impl LoggerConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets log levels for modules, such as "info,ckb-sync=debug".
+    pub fn filter<T: ToString>(mut self, filter: T) -> Self {
+        self.filter = Some(filter.to_string());
+        self
+    }
+
+    pub fn to_stdout(mut self, to_stdout: bool) -> Self {
+        self.to_stdout = Some(to_stdout);
+        self
+    }
+
+    pub fn to_file(mut self, to_file: bool) -> Self {
+        self.to_file = Some(to_file);
+        self
+    }
+
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn build_main(self) -> rpc::MainLoggerConfig {
+        rpc::MainLoggerConfig {
+            filter: self.filter,
+            to_stdout: self.to_stdout,
+            to_file: self.to_file,
+            color: self.color,
+        }
+    }
+
+    /// An extra logger only has a filter, and the filter is required.
+    pub fn build_extra(self) -> Result<rpc::ExtraLoggerConfig> {
+        if self.to_stdout.is_some() || self.to_file.is_some() || self.color.is_some() {
+            return Err(Error::invalid_argument(
+                "an extra logger only supports the filter option",
+            ));
+        }
+        let filter = self
+            .filter
+            .ok_or_else(|| Error::invalid_argument("an extra logger requires a filter"))?;
+        Ok(rpc::ExtraLoggerConfig { filter })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::serde_json::{self, json};
+
+    use super::*;
+
+    #[test]
+    fn main_logger_config_keeps_unset_options() {
+        let config = LoggerConfigBuilder::new()
+            .filter("info,ckb-sync=debug")
+            .color(false)
+            .build_main();
+        let expected = json!({
+            "filter": "info,ckb-sync=debug",
+            "to_stdout": null,
+            "to_file": null,
+            "color": false,
+        });
+        assert_eq!(serde_json::to_value(config).unwrap(), expected);
+    }
+
+    #[test]
+    fn extra_logger_config_only_has_a_filter() {
+        let config = LoggerConfigBuilder::new()
+            .filter("ckb-rpc=trace")
+            .build_extra()
+            .unwrap();
+        let expected = json!({ "filter": "ckb-rpc=trace" });
+        assert_eq!(serde_json::to_value(config).unwrap(), expected);
+        let result = LoggerConfigBuilder::new().build_extra();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        let result = LoggerConfigBuilder::new()
+            .filter("info")
+            .to_file(true)
+            .build_extra();
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::{result, sync::Arc};
+
+use parking_lot::RwLock;
+
+pub use jsonrpc_core::{Error as RpcError, Params, Value};
+
+/// Intercepts the requests and the responses of the HTTP client.
+///
+/// Middlewares work on the raw JSON-RPC messages, the method is the name in the RPC
+/// (for example, both `Client::get_block` and `Client::get_block_packed` call `get_block`).
+pub trait Middleware: Send + Sync {
+    /// Called before a request is sent.
+    ///
+    /// Mutating `params` changes what is sent to the node.
+    fn before(&self, _method: &str, _params: &mut Params) {}
+
+    /// Called after a response is received from the node.
+    ///
+    /// Mutating `result` changes what is returned to the caller.
+    fn after(&self, _method: &str, _result: &mut result::Result<Value, RpcError>) {}
+}
+
+// The middlewares are shared between the client and its HTTP transport, and they are
+// invoked in the order in which they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Middlewares {
+    inner: Arc<RwLock<Vec<Arc<dyn Middleware>>>>,
+}
+
+impl Middlewares {
+    // Copies the current middlewares, the copy is changed independently.
+    pub(crate) fn fork(&self) -> Self {
+        let inner = Arc::new(RwLock::new(self.inner.read().clone()));
+        Self { inner }
+    }
+
+    pub(crate) fn add(&self, middleware: Arc<dyn Middleware>) {
+        self.inner.write().push(middleware);
+    }
+
+    pub(crate) fn before(&self, method: &str, params: &mut Params) {
+        for middleware in self.inner.read().iter() {
+            middleware.before(method, params);
+        }
+    }
+
+    pub(crate) fn after(&self, method: &str, result: &mut result::Result<Value, RpcError>) {
+        for middleware in self.inner.read().iter() {
+            middleware.after(method, result);
+        }
+    }
+}
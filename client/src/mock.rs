@@ -0,0 +1,337 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Mock nodes for the tests.
+
+use std::{
+    io::{BufRead as _, BufReader, Write as _},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    result,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    header, rt::Stream as _, service::service_fn, Body, Request, Response, Server, StatusCode,
+};
+use jsonrpc_core::{
+    self as rpc,
+    futures::{future, sync::oneshot, Future},
+};
+use jsonrpc_server_utils::tokio::runtime::Runtime as RawRuntime01;
+use parking_lot::Mutex;
+use url::Url;
+
+use crate::Client;
+
+pub(crate) type CallResult = result::Result<rpc::Value, rpc::Error>;
+
+type Handler = dyn Fn(&str, &rpc::Params) -> CallResult + Send + Sync;
+
+// A method call which is received by the mock node.
+#[derive(Debug, Clone)]
+pub(crate) struct Recorded {
+    pub(crate) method: String,
+    pub(crate) params: rpc::Params,
+    // the `User-Agent` header of the HTTP request
+    pub(crate) user_agent: Option<String>,
+    // whether the call is in a batch request
+    pub(crate) in_batch: bool,
+}
+
+// A JSON-RPC node over HTTP, the results of the calls are decided by the handler.
+//
+// The responses of a batch are in the reversed order, so the clients have to match them by
+// the ids.
+pub(crate) struct MockHttpServer {
+    url: Url,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+    stopper: Option<oneshot::Sender<()>>,
+    rt: Option<RawRuntime01>,
+}
+
+impl MockHttpServer {
+    pub(crate) fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &rpc::Params) -> CallResult + Send + Sync + 'static,
+    {
+        let handler: Arc<Handler> = Arc::new(handler);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests_cloned = Arc::clone(&requests);
+        let new_service = move || {
+            let handler = Arc::clone(&handler);
+            let requests = Arc::clone(&requests_cloned);
+            service_fn(move |request: Request<Body>| {
+                let handler = Arc::clone(&handler);
+                let requests = Arc::clone(&requests);
+                let user_agent = request
+                    .headers()
+                    .get(header::USER_AGENT)
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+                request.into_body().concat2().map(move |body| {
+                    let body = String::from_utf8_lossy(&body);
+                    match rpc::serde_from_str::<rpc::Request>(&body) {
+                        Ok(request) => {
+                            let call = |call, in_batch| {
+                                respond(&*handler, &requests, &user_agent, in_batch, call)
+                            };
+                            let response = match request {
+                                rpc::Request::Single(single) => {
+                                    rpc::Response::Single(call(single, false))
+                                }
+                                rpc::Request::Batch(calls) => {
+                                    let outputs = calls
+                                        .into_iter()
+                                        .rev()
+                                        .map(|single| call(single, true))
+                                        .collect();
+                                    rpc::Response::Batch(outputs)
+                                }
+                            };
+                            let body = rpc::to_string(&response).expect("serialize a response");
+                            Response::new(Body::from(body))
+                        }
+                        Err(_) => Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::empty())
+                            .expect("build a response"),
+                    }
+                })
+            })
+        };
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(new_service);
+        let url = Url::parse(&format!("http://{}", server.local_addr())).expect("parse the url");
+        let (stopper, receiver) = oneshot::channel::<()>();
+        let server = server
+            .with_graceful_shutdown(receiver.then(|_| future::ok::<_, ()>(())))
+            .map_err(|err| panic!("mock http server failed: {}", err));
+        let mut rt = RawRuntime01::new().expect("create a runtime for the mock http server");
+        rt.spawn(server);
+        Self {
+            url,
+            requests,
+            stopper: Some(stopper),
+            rt: Some(rt),
+        }
+    }
+
+    pub(crate) fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    pub(crate) fn client(&self) -> Client {
+        Client::builder()
+            .http(self.url())
+            .worker_threads(1)
+            .build()
+            .expect("build a client for the mock http server")
+    }
+
+    // The method calls in the order they are received.
+    pub(crate) fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().clone()
+    }
+
+    pub(crate) fn methods(&self) -> Vec<String> {
+        self.requests().into_iter().map(|req| req.method).collect()
+    }
+}
+
+impl Drop for MockHttpServer {
+    fn drop(&mut self) {
+        if let Some(stopper) = self.stopper.take() {
+            let _result = stopper.send(());
+        }
+        if let Some(rt) = self.rt.take() {
+            rt.shutdown_now()
+                .wait()
+                .expect("shutdown the mock http server");
+        }
+    }
+}
+
+fn respond(
+    handler: &Handler,
+    requests: &Mutex<Vec<Recorded>>,
+    user_agent: &Option<String>,
+    in_batch: bool,
+    call: rpc::Call,
+) -> rpc::Output {
+    match call {
+        rpc::Call::MethodCall(call) => {
+            requests.lock().push(Recorded {
+                method: call.method.clone(),
+                params: call.params.clone(),
+                user_agent: user_agent.clone(),
+                in_batch,
+            });
+            let result = handler(&call.method, &call.params);
+            rpc::Output::from(result, call.id, call.jsonrpc)
+        }
+        _ => rpc::Output::from(
+            Err(rpc::Error::invalid_request()),
+            rpc::Id::Null,
+            Some(rpc::Version::V2),
+        ),
+    }
+}
+
+// A JSON-RPC node over TCP which only supports the subscriptions, the messages are published
+// by the tests.
+pub(crate) struct MockTcpServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+    // the connections and the ids of their subscriptions
+    subscriptions: Arc<Mutex<Vec<(TcpStream, String)>>>,
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockTcpServer {
+    pub(crate) fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind the mock tcp server");
+        let addr = listener
+            .local_addr()
+            .expect("get the address of the mock tcp server");
+        let server = Self {
+            addr,
+            requests: Default::default(),
+            subscriptions: Default::default(),
+            connections: Default::default(),
+            stopped: Default::default(),
+        };
+        let requests = Arc::clone(&server.requests);
+        let subscriptions = Arc::clone(&server.subscriptions);
+        let connections = Arc::clone(&server.connections);
+        let stopped = Arc::clone(&server.stopped);
+        let next_id = Arc::new(AtomicU64::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = stream.expect("accept a connection");
+                connections
+                    .lock()
+                    .push(stream.try_clone().expect("clone a connection"));
+                let requests = Arc::clone(&requests);
+                let subscriptions = Arc::clone(&subscriptions);
+                let next_id = Arc::clone(&next_id);
+                thread::spawn(move || serve(stream, &requests, &subscriptions, &next_id));
+            }
+        });
+        server
+    }
+
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub(crate) fn client(&self) -> Client {
+        Client::builder()
+            .tcp(self.addr)
+            .worker_threads(1)
+            .build()
+            .expect("build a client for the mock tcp server")
+    }
+
+    // Waits until the node has the subscriptions.
+    //
+    // `subscribe` returns before the node receives the request, and the node drops the
+    // messages which are published before that.
+    pub(crate) fn wait_for_subscriptions(&self, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while self.subscriptions.lock().len() < count {
+            assert!(Instant::now() < deadline, "no {} subscriptions", count);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // Sends a message to all subscriptions.
+    pub(crate) fn publish(&self, msg: &str) {
+        for (stream, id) in self.subscriptions.lock().iter_mut() {
+            let notification = rpc::serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "subscribe",
+                "params": { "subscription": id, "result": msg },
+            });
+            let _result = writeln!(stream, "{}", notification);
+        }
+    }
+
+    pub(crate) fn methods(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .iter()
+            .map(|req| req.method.clone())
+            .collect()
+    }
+}
+
+impl Drop for MockTcpServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake up the listener
+        let _result = TcpStream::connect(self.addr);
+        for stream in self.connections.lock().drain(..) {
+            let _result = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    requests: &Mutex<Vec<Recorded>>,
+    subscriptions: &Mutex<Vec<(TcpStream, String)>>,
+    next_id: &AtomicU64,
+) {
+    let mut writer = stream.try_clone().expect("clone a connection");
+    for line in BufReader::new(stream).lines() {
+        let call = match line.map(|line| rpc::serde_from_str::<rpc::MethodCall>(&line)) {
+            Ok(Ok(call)) => call,
+            _ => break,
+        };
+        requests.lock().push(Recorded {
+            method: call.method.clone(),
+            params: call.params.clone(),
+            user_agent: None,
+            in_batch: false,
+        });
+        let mut subscriptions = subscriptions.lock();
+        let result = match call.method.as_str() {
+            "subscribe" => {
+                let id = format!("0x{:x}", next_id.fetch_add(1, Ordering::SeqCst));
+                let stream = writer.try_clone().expect("clone a connection");
+                subscriptions.push((stream, id.clone()));
+                Ok(id.into())
+            }
+            "unsubscribe" => {
+                let id = call.params.clone().parse::<(String,)>().map(|(id,)| id);
+                id.map(|id| {
+                    subscriptions.retain(|(_, existed)| *existed != id);
+                    true.into()
+                })
+            }
+            _ => Err(rpc::Error::method_not_found()),
+        };
+        let output = rpc::Output::from(result, call.id, call.jsonrpc);
+        let response = rpc::to_string(&output).expect("serialize a response");
+        if writeln!(writer, "{}", response).is_err() {
+            break;
+        }
+    }
+}
+
+// Serializes a value as the result of a call.
+pub(crate) fn to_value<T: rpc::serde::Serialize>(value: T) -> rpc::Value {
+    rpc::serde_json::to_value(value).expect("serialize a result")
+}
@@ -0,0 +1,162 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::str::FromStr as _;
+
+use uckb_jsonrpc_core::types::{core, fixed, packed, prelude::*};
+
+use crate::error::{Error, Result};
+
+fn trim_0x(input: &str) -> Result<&str> {
+    if let Some(stripped) = input.strip_prefix("0x") {
+        Ok(stripped)
+    } else {
+        Err(Error::parse(format!(
+            "hex string should start with \"0x\", but got {:?}",
+            input
+        )))
+    }
+}
+
+pub fn parse_hex(input: &str) -> Result<Vec<u8>> {
+    let hex = trim_0x(input)?;
+    if hex.len() % 2 != 0 {
+        return Err(Error::parse(format!(
+            "hex string should have an even length, but got {:?}",
+            input
+        )));
+    }
+    let mut bytes = vec![0u8; hex.len() / 2];
+    faster_hex::hex_decode(hex.as_bytes(), &mut bytes)
+        .map_err(|err| Error::parse(format!("invalid hex string {:?}: {}", input, err)))?;
+    Ok(bytes)
+}
+
+pub fn parse_h256(input: &str) -> Result<fixed::H256> {
+    let hex = trim_0x(input)?;
+    fixed::H256::from_str(hex)
+        .map_err(|err| Error::parse(format!("invalid 32-bytes hash {:?}: {}", input, err)))
+}
+
+// The format of an out point is "0x{tx_hash}-{index}".
+pub fn parse_out_point(input: &str) -> Result<packed::OutPoint> {
+    let mut parts = input.splitn(2, '-');
+    let tx_hash = parts.next().map(parse_h256).transpose()?;
+    let index = parts
+        .next()
+        .map(|index| {
+            index.parse::<u32>().map_err(|err| {
+                Error::parse(format!("invalid out point index {:?}: {}", index, err))
+            })
+        })
+        .transpose()?;
+    if let (Some(tx_hash), Some(index)) = (tx_hash, index) {
+        let out_point = packed::OutPoint::new_builder()
+            .tx_hash(tx_hash.pack())
+            .index(index.pack())
+            .build();
+        Ok(out_point)
+    } else {
+        Err(Error::parse(format!(
+            "out point should be \"0x{{tx_hash}}-{{index}}\", but got {:?}",
+            input
+        )))
+    }
+}
+
+// The same names as in the JSON-RPC.
+pub fn parse_script_hash_type(input: &str) -> Result<core::ScriptHashType> {
+    match input {
+        "data" => Ok(core::ScriptHashType::Data),
+        "type" => Ok(core::ScriptHashType::Type),
+        // Added in CKB2021, the types of this version don't have it.
+        "data1" => Err(Error::parse(
+            "script hash type \"data1\" is not supported before CKB2021",
+        )),
+        _ => Err(Error::parse(format!(
+            "script hash type should be \"data\" or \"type\", but got {:?}",
+            input
+        ))),
+    }
+}
+
+// The same names as in the JSON-RPC.
+pub fn parse_dep_type(input: &str) -> Result<core::DepType> {
+    match input {
+        "code" => Ok(core::DepType::Code),
+        "dep_group" => Ok(core::DepType::DepGroup),
+        _ => Err(Error::parse(format!(
+            "dep type should be \"code\" or \"dep_group\", but got {:?}",
+            input
+        ))),
+    }
+}
+
+pub fn parse_script_hex(input: &str) -> Result<packed::Script> {
+    let bytes = parse_hex(input)?;
+    packed::Script::from_slice(&bytes)
+        .map_err(|err| Error::parse(format!("invalid molecule script: {}", err)))
+}
+
+pub fn parse_transaction_hex(input: &str) -> Result<packed::Transaction> {
+    let bytes = parse_hex(input)?;
+    packed::Transaction::from_slice(&bytes)
+        .map_err(|err| Error::parse(format!("invalid molecule transaction: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::serde_json;
+    use uckb_jsonrpc_core::types::rpc;
+
+    use super::*;
+    use crate::convert::{dep_type_to_str, script_hash_type_to_str};
+
+    #[test]
+    fn script_hash_type_round_trip() {
+        for hash_type in &[core::ScriptHashType::Data, core::ScriptHashType::Type] {
+            let name = script_hash_type_to_str(*hash_type);
+            assert!(parse_script_hash_type(name).unwrap() == *hash_type);
+            // the same names as in the JSON-RPC
+            let json = serde_json::to_value(rpc::ScriptHashType::from(*hash_type)).unwrap();
+            assert_eq!(json, name);
+        }
+    }
+
+    #[test]
+    fn data1_is_explained() {
+        match parse_script_hash_type("data1") {
+            Err(Error::Parse(message)) => assert!(message.contains("CKB2021"), "{}", message),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("\"data1\" should be rejected"),
+        }
+    }
+
+    #[test]
+    fn unknown_script_hash_type_is_rejected() {
+        for input in &["", "Data", "TYPE", "data2"] {
+            assert!(matches!(
+                parse_script_hash_type(input),
+                Err(Error::Parse(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn dep_type_round_trip() {
+        for dep_type in &[core::DepType::Code, core::DepType::DepGroup] {
+            let name = dep_type_to_str(*dep_type);
+            assert!(parse_dep_type(name).unwrap() == *dep_type);
+            let json = serde_json::to_value(rpc::DepType::from(*dep_type)).unwrap();
+            assert_eq!(json, name);
+        }
+        for input in &["", "depgroup", "dep-group", "Code"] {
+            assert!(matches!(parse_dep_type(input), Err(Error::Parse(_))));
+        }
+    }
+}
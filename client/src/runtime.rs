@@ -6,11 +6,24 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::{future::Future, result, sync::Arc};
+use std::{future::Future, sync::Arc, time::Duration};
 
-use jsonrpc_server_utils::tokio::{prelude::Future as Future01, runtime::Runtime as RawRuntime01};
+use futures::{
+    compat::Future01CompatExt as _,
+    future::{self, Aborted},
+    FutureExt as _, TryFutureExt as _,
+};
+use jsonrpc_core::futures::sync::oneshot as oneshot01;
+use jsonrpc_server_utils::tokio::{
+    executor::{DefaultExecutor, Executor as _},
+    prelude::Future as Future01,
+    runtime::Runtime as RawRuntime01,
+};
 use parking_lot::RwLock;
-use tokio::{runtime::Runtime as RawRuntime, task::JoinHandle};
+use tokio::{runtime::Runtime as RawRuntime, sync::oneshot, task::JoinHandle, time};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{Error, Result};
 
 #[derive(Clone)]
 pub(crate) struct Runtime {
@@ -26,14 +39,6 @@ impl Runtime {
         }
     }
 
-    pub(crate) fn block_on<F>(&self, future: F) -> F::Output
-    where
-        F: Future,
-    {
-        log::trace!("block on a future");
-        self.core.block_on(future)
-    }
-
     pub(crate) fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
     where
         F: Future + Send + 'static,
@@ -43,14 +48,91 @@ impl Runtime {
         self.core.spawn(future)
     }
 
-    pub(crate) fn block_on_01<F>(&self, future: F) -> result::Result<F::Item, F::Error>
+    pub(crate) fn spawn_blocking<F, R>(&self, func: F) -> JoinHandle<R>
     where
-        F: Future01 + Send + 'static,
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        log::trace!("spawn a blocking function");
+        self.core.spawn_blocking(func)
+    }
+
+    // Sleeps until the duration passes, returns `false` if the token is cancelled before that.
+    pub(crate) fn sleep_cancellable(&self, duration: Duration, token: &CancellationToken) -> bool {
+        let token = token.clone();
+        let handle = self.spawn(async move {
+            tokio::select! {
+                _ = token.cancelled() => false,
+                _ = time::sleep(duration) => true,
+            }
+        });
+        futures::executor::block_on(handle).unwrap_or(false)
+    }
+
+    // Returns `Error::Cancelled` if the legacy runtime drops the future before it completes,
+    // such as when the runtime is shutting down.
+    //
+    // Returns `Error::Runtime` if it's called from a worker of a legacy runtime, the worker
+    // may be the one which should drive the future, so blocking it could deadlock.
+    pub(crate) fn block_on_01<F>(&self, future: F) -> Result<F::Item>
+    where
+        F: Future01<Error = Error> + Send + 'static,
         F::Item: Send + 'static,
-        F::Error: Send + 'static,
     {
         log::trace!("block on a legacy future");
-        self.legacy_support.write().block_on(future)
+        // The workers of a legacy runtime set the default executor, other threads don't.
+        if DefaultExecutor::current().status().is_ok() {
+            return Err(Error::runtime(
+                "can't block on a future in a worker of the legacy runtime",
+            ));
+        }
+        // Spawn the future instead of blocking on the legacy runtime directly, which requires
+        // the write lock, so concurrent calls won't block each other.
+        let (sender, receiver) = oneshot01::channel();
+        let future = future.then(move |result| {
+            let _ = sender.send(result);
+            Ok(())
+        });
+        self.legacy_support.read().executor().spawn(future);
+        receiver.wait().unwrap_or_else(|oneshot01::Canceled| {
+            log::warn!("legacy runtime dropped a spawned future");
+            Err(Error::Cancelled)
+        })
+    }
+
+    // Returns `None` if the future was cancelled before it completed.
+    pub(crate) fn block_on_01_cancellable<F>(
+        &self,
+        future: F,
+        token: CancellationToken,
+    ) -> Option<Result<F::Item>>
+    where
+        F: Future01<Error = Error> + Send + 'static,
+        F::Item: Send + 'static,
+    {
+        log::trace!("block on a cancellable legacy future");
+        let (future, handle) = future::abortable(future.compat());
+        let (done_sender, done_receiver) = oneshot::channel::<()>();
+        self.spawn(async move {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    log::trace!("cancel a legacy future");
+                    handle.abort();
+                }
+                _ = done_receiver => {}
+            }
+        });
+        let future = future
+            .map(|result| match result {
+                Ok(Ok(item)) => Ok(Some(item)),
+                Ok(Err(err)) => Err(err),
+                Err(Aborted) => Ok(None),
+            })
+            .boxed()
+            .compat();
+        let result = self.block_on_01(future);
+        let _ = done_sender.send(());
+        result.transpose()
     }
 
     pub(crate) fn spawn_01<F>(&self, future: F)
@@ -60,6 +142,50 @@ impl Runtime {
         F::Error: Send,
     {
         log::trace!("spawn a legacy future");
-        self.legacy_support.write().spawn(future);
+        self.legacy_support.read().executor().spawn(future);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::result;
+
+    use jsonrpc_core::futures::future::{self as future01, lazy};
+
+    use super::*;
+
+    fn runtime() -> Runtime {
+        let core = RawRuntime::new().expect("create a runtime");
+        let legacy_support = RawRuntime01::new().expect("create a legacy runtime");
+        Runtime::new(Arc::new(core), Arc::new(RwLock::new(legacy_support)))
+    }
+
+    #[test]
+    fn block_on_01_returns_the_result() {
+        let rt = runtime();
+        assert_eq!(rt.block_on_01(future01::ok::<_, Error>(1)).unwrap(), 1);
+        let result = rt.block_on_01(future01::err::<(), _>(Error::Cancelled));
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn block_on_01_reports_a_dropped_future_as_cancelled() {
+        let rt = runtime();
+        let fut = lazy(|| -> result::Result<(), Error> { panic!("drop the future") });
+        assert!(matches!(rt.block_on_01(fut), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn block_on_01_refuses_to_block_a_legacy_worker() {
+        let rt = runtime();
+        let rt_cloned = rt.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        rt.spawn_01(lazy(move || {
+            let result = rt_cloned.block_on_01(future01::ok::<_, Error>(1));
+            sender.send(result).unwrap();
+            Ok(())
+        }));
+        let result = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(result, Err(Error::Runtime(_))));
     }
 }
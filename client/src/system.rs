@@ -0,0 +1,1692 @@
+// Copyright (C) 2019-2020 Boyu Yang
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use uckb_jsonrpc_core::{
+    blake2b::{new_blake2b, Blake2bBuilder, CKB_HASH_PERSONALIZATION},
+    secp256k1::{Message, Privkey, Pubkey, Signature},
+    types::{
+        bytes::Bytes,
+        core, fixed, packed,
+        prelude::*,
+        rpc,
+        utilities::{merkle_root, MerkleProof},
+    },
+};
+
+use crate::{
+    error::{Error, Result},
+    GenesisInfo,
+};
+
+pub const DEFAULT_PERSONAL: &[u8] = CKB_HASH_PERSONALIZATION;
+
+pub const SECP256K1_SIGNATURE_SIZE: usize = 65;
+
+pub const ZERO_H256: fixed::H256 = fixed::H256([0u8; 32]);
+
+/// The data hash of the `secp256k1_blake160_sighash_all` script.
+pub const SECP256K1_BLAKE160_SIGHASH_ALL_DATA_HASH: fixed::H256 = fixed::H256([
+    0x70, 0x9f, 0x3f, 0xda, 0x12, 0xf5, 0x61, 0xcf, 0xac, 0xf9, 0x22, 0x73, 0xc5, 0x7a, 0x98, 0xfe,
+    0xde, 0x18, 0x8a, 0x3f, 0x1a, 0x59, 0xb1, 0xf8, 0x88, 0xd1, 0x13, 0xf9, 0xcc, 0xe0, 0x86, 0x49,
+]);
+
+/// The data hash of the DAO type script.
+pub const DAO_DATA_HASH: fixed::H256 = fixed::H256([
+    0x32, 0x06, 0x4a, 0x14, 0xce, 0x10, 0xd9, 0x5d, 0x4b, 0x73, 0x43, 0x05, 0x4c, 0xc1, 0x9d, 0x73,
+    0xb2, 0x5b, 0x16, 0xae, 0x61, 0xa6, 0xc6, 0x81, 0x01, 0x1c, 0xa7, 0x81, 0xa6, 0x0c, 0x79, 0x23,
+]);
+
+// The type hash of the DAO type script on the chains which follow the chain spec of CKB, it's
+// the type id of the output#2 of the genesis cellbase.
+const DAO_TYPE_HASH: fixed::H256 = fixed::H256([
+    0x82, 0xd7, 0x6d, 0x1b, 0x75, 0xfe, 0x2f, 0xd9, 0xa2, 0x7d, 0xfb, 0xaa, 0x65, 0xa0, 0x39, 0x22,
+    0x1a, 0x38, 0x0d, 0x76, 0xc9, 0x26, 0xf3, 0x78, 0xd3, 0xf8, 0x1c, 0xf3, 0xe7, 0xe1, 0x3f, 0x2e,
+]);
+
+/// The cellbase maturity of the mainnet and the testnet, 4 epochs.
+pub const DEFAULT_CELLBASE_MATURITY: core::EpochNumberWithFraction =
+    core::EpochNumberWithFraction::new_unchecked(4, 0, 1);
+
+// The personalization parameter of blake2b is at most 16 bytes.
+const MAX_PERSONAL_LEN: usize = 16;
+
+pub fn blake2b_256_with_personal(data: &[u8], personal: &[u8]) -> Result<[u8; 32]> {
+    let mut hash = [0u8; 32];
+    blake2b_with_personal(data, personal, &mut hash)?;
+    Ok(hash)
+}
+
+pub fn blake2b_160_with_personal(data: &[u8], personal: &[u8]) -> Result<[u8; 20]> {
+    let hash = blake2b_256_with_personal(data, personal)?;
+    let mut result = [0u8; 20];
+    result.copy_from_slice(&hash[..20]);
+    Ok(result)
+}
+
+pub fn blake2b_160(data: &[u8]) -> [u8; 20] {
+    blake2b_160_with_personal(data, DEFAULT_PERSONAL).expect("default personal is valid")
+}
+
+fn blake2b_with_personal(data: &[u8], personal: &[u8], output: &mut [u8]) -> Result<()> {
+    if personal.len() > MAX_PERSONAL_LEN {
+        return Err(Error::invalid_argument(format!(
+            "blake2b personal should be at most {} bytes, but got {}",
+            MAX_PERSONAL_LEN,
+            personal.len()
+        )));
+    }
+    let mut hasher = Blake2bBuilder::new(output.len()).personal(personal).build();
+    hasher.update(data);
+    hasher.finalize(output);
+    Ok(())
+}
+
+// The lock arg of secp256k1 is the blake160 of the compressed public key.
+pub fn pubkey_to_lock_arg(pubkey: &Pubkey) -> packed::Bytes {
+    Bytes::from(blake2b_160(&pubkey.serialize()).to_vec()).pack()
+}
+
+// The type id is the hash of the first input and the index of the output which uses it.
+pub fn calculate_type_id(first_input: &packed::CellInput, output_index: u64) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    let mut hasher = new_blake2b();
+    hasher.update(first_input.as_slice());
+    hasher.update(&output_index.to_le_bytes());
+    hasher.finalize(&mut hash);
+    hash
+}
+
+/// Puts zero-filled lock placeholders into the witnesses, so the size of the transaction is
+/// the same as the size after it is signed with secp256k1.
+///
+/// `group_sizes` are the counts of inputs of each lock group, the groups should be
+/// contiguous and in the order of the inputs. The first witness of each group is set to a
+/// `WitnessArgs` whose `lock` is a 65-bytes placeholder, the `input_type` and the
+/// `output_type` in the existing witness are kept.
+pub fn reserve_witness_space(tx: &mut packed::Transaction, group_sizes: &[usize]) -> Result<()> {
+    let inputs_len = tx.raw().inputs().len();
+    let groups_len: usize = group_sizes.iter().sum();
+    if groups_len != inputs_len {
+        return Err(Error::invalid_argument(format!(
+            "the groups have {} inputs in total, but the transaction has {} inputs",
+            groups_len, inputs_len
+        )));
+    }
+    let mut witnesses = tx.witnesses().into_iter().collect::<Vec<_>>();
+    if witnesses.len() < inputs_len {
+        witnesses.resize(inputs_len, Default::default());
+    }
+    let placeholder = Some(Bytes::from(vec![0u8; SECP256K1_SIGNATURE_SIZE])).pack();
+    let mut index = 0;
+    for size in group_sizes.iter().filter(|size| **size > 0) {
+        let witness = &witnesses[index];
+        let witness_args = if witness.is_empty() {
+            packed::WitnessArgs::default()
+        } else {
+            packed::WitnessArgs::from_slice(&witness.raw_data()).map_err(|err| {
+                Error::invalid_argument(format!("witness#{} is not a WitnessArgs: {}", index, err))
+            })?
+        };
+        let witness_args = witness_args.as_builder().lock(placeholder.clone()).build();
+        witnesses[index] = witness_args.as_bytes().pack();
+        index += size;
+    }
+    *tx = tx
+        .clone()
+        .as_builder()
+        .witnesses(packed::BytesVec::new_builder().set(witnesses).build())
+        .build();
+    Ok(())
+}
+
+/// Checks the obvious construction bugs of a transaction, which would be rejected by the node.
+///
+/// The checks are:
+/// - The inputs, the outputs and the cell deps are not empty.
+/// - Each output has its data.
+/// - Each output has enough capacity for itself and its data.
+pub fn validate_transaction_structure(tx: &packed::Transaction) -> Result<()> {
+    let raw = tx.raw();
+    if raw.inputs().is_empty() {
+        return Err(Error::invalid_argument("the transaction has no inputs"));
+    }
+    if raw.outputs().is_empty() {
+        return Err(Error::invalid_argument("the transaction has no outputs"));
+    }
+    // The lock scripts of the inputs require cell deps to load their code.
+    if raw.cell_deps().is_empty() {
+        return Err(Error::invalid_argument("the transaction has no cell deps"));
+    }
+    if raw.outputs().len() != raw.outputs_data().len() {
+        return Err(Error::invalid_argument(format!(
+            "the transaction has {} outputs but {} outputs data",
+            raw.outputs().len(),
+            raw.outputs_data().len()
+        )));
+    }
+    for (index, (output, data)) in raw
+        .outputs()
+        .into_iter()
+        .zip(raw.outputs_data())
+        .enumerate()
+    {
+        let min_capacity = occupied_capacity(&output, data.raw_data().len())?;
+        let capacity: core::Capacity = output.capacity().unpack();
+        if capacity < min_capacity {
+            return Err(Error::invalid_argument(format!(
+                "the output#{} has {} but occupies {}",
+                index, capacity, min_capacity
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The size of the molecule encoding of a transaction.
+pub fn transaction_size(tx: &packed::Transaction) -> usize {
+    tx.as_slice().len()
+}
+
+/// The size which a transaction takes in a block, which is used to calculate the fee.
+///
+/// It's the molecule size plus 4 bytes, which is the offset of the transaction in the block.
+pub fn transaction_weight(tx: &packed::Transaction) -> u64 {
+    tx.serialized_size_in_block() as u64
+}
+
+/// Calculates the minimal capacity of an output with its data.
+///
+/// Each byte of the capacity field, the lock script, the type script and the data occupies
+/// 1 CKBytes, which is 10^8 shannons.
+pub fn occupied_capacity(output: &packed::CellOutput, data_len: usize) -> Result<core::Capacity> {
+    core::Capacity::bytes(data_len)
+        .and_then(|data_capacity| output.occupied_capacity(data_capacity))
+        .map_err(|err| Error::invalid_argument(format!("capacity overflow: {}", err)))
+}
+
+/// Calculates the message to sign for a lock group with secp256k1_blake160_sighash_all.
+///
+/// `group` is the indexes of the inputs in the lock group, and `witnesses` are the witnesses
+/// of the transaction, indexed as the inputs. The witnesses in `tx` are ignored, only its hash
+/// is used, so the message can be calculated over placeholder witnesses before they are put
+/// into the transaction. The lock of the first witness in the group is replaced with a
+/// 65-bytes zero-filled placeholder. The message is the blake2b hash of, in order:
+/// - The transaction hash.
+/// - The length (8 bytes, little-endian) and the content of the first witness in the group.
+/// - The lengths and the contents of the other witnesses in the group.
+/// - The lengths and the contents of the witnesses which don't belong to any input.
+///
+/// It returns an error instead of panicking if the group is empty, an index is out of range
+/// or the first witness of the group is missing.
+pub fn tx_signing_message(
+    tx: &packed::Transaction,
+    group: &[usize],
+    witnesses: &[packed::WitnessArgs],
+) -> Result<fixed::H256> {
+    let witnesses = witnesses
+        .iter()
+        .map(|witness| witness.as_bytes())
+        .collect::<Vec<_>>();
+    raw_signing_message(tx, group, &witnesses)
+}
+
+// The other witnesses in a lock group are usually empty, which isn't a `WitnessArgs`, so the
+// message is calculated over the raw witnesses.
+fn raw_signing_message(
+    tx: &packed::Transaction,
+    group: &[usize],
+    witnesses: &[Bytes],
+) -> Result<fixed::H256> {
+    let inputs_len = tx.raw().inputs().len();
+    if let Some(index) = group.iter().find(|index| **index >= inputs_len) {
+        return Err(Error::invalid_argument(format!(
+            "input#{} is out of range, the transaction has {} inputs",
+            index, inputs_len
+        )));
+    }
+    let (first, others) = group
+        .split_first()
+        .ok_or_else(|| Error::invalid_argument("the group is empty"))?;
+    let first_witness = witnesses
+        .get(*first)
+        .ok_or_else(|| Error::invalid_argument(format!("witness#{} is missing", first)))?;
+    let witness_args = if first_witness.is_empty() {
+        packed::WitnessArgs::default()
+    } else {
+        packed::WitnessArgs::from_slice(first_witness).map_err(|err| {
+            Error::invalid_argument(format!("witness#{} is not a WitnessArgs: {}", first, err))
+        })?
+    };
+    let placeholder = Some(Bytes::from(vec![0u8; SECP256K1_SIGNATURE_SIZE])).pack();
+    let witness_args = witness_args.as_builder().lock(placeholder).build();
+    let mut hasher = new_blake2b();
+    hasher.update(tx.calc_tx_hash().as_slice());
+    let mut update_witness = |data: &[u8]| {
+        hasher.update(&(data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    };
+    update_witness(witness_args.as_slice());
+    for index in others {
+        if let Some(witness) = witnesses.get(*index) {
+            update_witness(witness);
+        }
+    }
+    for witness in witnesses.iter().skip(inputs_len) {
+        update_witness(witness);
+    }
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    Ok(fixed::H256(hash))
+}
+
+/// Signs transactions with an external signer, so the private keys are not required.
+pub trait TransactionBuilderExt {
+    /// Builds the transaction and signs each lock group with secp256k1_blake160_sighash_all.
+    ///
+    /// The lock groups are taken from the witnesses, as in the transactions signed by CKB:
+    /// each input with a non-empty witness starts a group, and the following inputs with
+    /// empty (or missing) witnesses belong to it. Without any witnesses, all inputs are one
+    /// group. The witness space is reserved (see [`reserve_witness_space`]), then the signer
+    /// receives the message of each group (see [`tx_signing_message`]) and returns a
+    /// recoverable signature, which is put into the lock of the first witness of the group.
+    ///
+    /// A signature which is not a valid recoverable signature is rejected, and an error of
+    /// the signer is returned as is.
+    fn sign_with<F>(self, signer: F) -> Result<packed::Transaction>
+    where
+        F: Fn(&fixed::H256) -> Result<[u8; SECP256K1_SIGNATURE_SIZE]>;
+}
+
+impl TransactionBuilderExt for core::TransactionBuilder {
+    fn sign_with<F>(self, signer: F) -> Result<packed::Transaction>
+    where
+        F: Fn(&fixed::H256) -> Result<[u8; SECP256K1_SIGNATURE_SIZE]>,
+    {
+        let mut tx = self.build().data();
+        let group_sizes = lock_group_sizes(&tx);
+        reserve_witness_space(&mut tx, &group_sizes)?;
+        let mut witnesses = tx
+            .witnesses()
+            .into_iter()
+            .map(|witness| witness.raw_data())
+            .collect::<Vec<_>>();
+        let mut signatures = Vec::new();
+        let mut start = 0;
+        for size in group_sizes {
+            let group = (start..start + size).collect::<Vec<_>>();
+            let message = raw_signing_message(&tx, &group, &witnesses)?;
+            let signature = signer(&message)?;
+            if !Signature::from_slice(&signature).is_ok_and(|sig| sig.is_valid()) {
+                return Err(Error::invalid_argument(format!(
+                    "the signature of input#{} is not a valid recoverable signature",
+                    start
+                )));
+            }
+            signatures.push((start, signature));
+            start += size;
+        }
+        for (index, signature) in signatures {
+            // The first witness of each group is a `WitnessArgs` after the space is reserved.
+            let witness_args = packed::WitnessArgs::from_slice(&witnesses[index])
+                .expect("witness space is reserved");
+            let lock = Some(Bytes::from(signature.to_vec())).pack();
+            witnesses[index] = witness_args.as_builder().lock(lock).build().as_bytes();
+        }
+        let tx = tx.as_builder().witnesses(witnesses.pack()).build();
+        Ok(tx)
+    }
+}
+
+// The sizes of the lock groups, which are marked by the non-empty witnesses.
+fn lock_group_sizes(tx: &packed::Transaction) -> Vec<usize> {
+    let inputs_len = tx.raw().inputs().len();
+    let witnesses = tx.witnesses();
+    let mut group_sizes = Vec::new();
+    for index in 0..inputs_len {
+        let starts_group = witnesses
+            .get(index)
+            .is_some_and(|witness| !witness.is_empty());
+        match group_sizes.last_mut() {
+            Some(size) if !starts_group => *size += 1,
+            _ => group_sizes.push(1),
+        }
+    }
+    group_sizes
+}
+
+/// Builds an alert, only the nodes whose versions are in `[min_version, max_version]` show it.
+pub fn build_alert(
+    id: u32,
+    cancel: u32,
+    min_version: Option<&str>,
+    max_version: Option<&str>,
+    priority: u32,
+    notice_until: u64,
+    message: &str,
+) -> packed::RawAlert {
+    let to_bytes =
+        |version: Option<&str>| version.map(|v| Bytes::from(v.as_bytes().to_vec())).pack();
+    packed::RawAlert::new_builder()
+        .id(id.pack())
+        .cancel(cancel.pack())
+        .min_version(to_bytes(min_version))
+        .max_version(to_bytes(max_version))
+        .priority(priority.pack())
+        .notice_until(notice_until.pack())
+        .message(Bytes::from(message.as_bytes().to_vec()).pack())
+        .build()
+}
+
+/// Signs an alert with the keys of the alert committee, the message is the alert hash.
+///
+/// The node accepts an alert if enough signatures are signed by the keys in its config.
+pub fn sign_alert(raw: packed::RawAlert, privkeys: &[Privkey]) -> Result<packed::Alert> {
+    let message = Message::from_slice(raw.calc_alert_hash().as_slice())
+        .expect("the alert hash is a valid message");
+    let signatures = privkeys
+        .iter()
+        .map(|privkey| {
+            privkey
+                .sign_recoverable(&message)
+                .map(|signature| Bytes::from(signature.serialize()).pack())
+                .map_err(|err| {
+                    Error::invalid_argument(format!("failed to sign the alert: {}", err))
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let alert = packed::Alert::new_builder()
+        .raw(raw)
+        .signatures(packed::BytesVec::new_builder().set(signatures).build())
+        .build();
+    Ok(alert)
+}
+
+/// Pays the fee of a transaction with the change output, to match the fee rate.
+///
+/// `inputs_capacity` is the total capacity of the resolved inputs, the current fee is what
+/// the outputs leave of it, and only the difference to the expected fee is taken from (or
+/// returned to) the change output, so calling it again with the same fee rate changes
+/// nothing. The size includes the witnesses, so it should be called after the witness space
+/// is reserved. Returns the fee.
+pub fn adjust_fee_to_rate(
+    tx: &mut packed::Transaction,
+    inputs_capacity: core::Capacity,
+    change_output_index: usize,
+    fee_rate: core::FeeRate,
+) -> Result<core::Capacity> {
+    let raw = tx.raw();
+    let change_output = raw.outputs().get(change_output_index).ok_or_else(|| {
+        Error::invalid_argument(format!(
+            "the change output#{} is out of range, the transaction has {} outputs",
+            change_output_index,
+            raw.outputs().len()
+        ))
+    })?;
+    let data_len = raw
+        .outputs_data()
+        .get(change_output_index)
+        .map(|data| data.raw_data().len())
+        .unwrap_or(0);
+    let min_capacity = occupied_capacity(&change_output, data_len)?;
+    let outputs_capacity = raw
+        .outputs()
+        .into_iter()
+        .try_fold(core::Capacity::zero(), |total, output| {
+            let capacity: core::Capacity = output.capacity().unpack();
+            total.safe_add(capacity)
+        })
+        .map_err(Error::invalid_argument)?;
+    let current_fee = inputs_capacity.safe_sub(outputs_capacity).map_err(|_| {
+        Error::invalid_argument(format!(
+            "the outputs have {} but the inputs only have {}",
+            outputs_capacity, inputs_capacity
+        ))
+    })?;
+    // The size doesn't change after the capacity is changed.
+    let fee = fee_rate.fee(tx.serialized_size_in_block());
+    let capacity: core::Capacity = change_output.capacity().unpack();
+    let capacity = capacity
+        .safe_add(current_fee)
+        .ok()
+        .and_then(|capacity| capacity.safe_sub(fee).ok())
+        .filter(|capacity| *capacity >= min_capacity)
+        .ok_or_else(|| {
+            Error::invalid_argument(format!(
+                "the change output has {} and the current fee is {}, \
+                 but it requires {} at least to pay the fee {}",
+                capacity, current_fee, min_capacity, fee
+            ))
+        })?;
+    let change_output = change_output.as_builder().capacity(capacity.pack()).build();
+    let mut outputs = raw.outputs().into_iter().collect::<Vec<_>>();
+    outputs[change_output_index] = change_output;
+    let raw = raw
+        .as_builder()
+        .outputs(packed::CellOutputVec::new_builder().set(outputs).build())
+        .build();
+    *tx = tx.clone().as_builder().raw(raw).build();
+    Ok(fee)
+}
+
+/// Finds the code hash (hash type is `type`) and the cell dep of the
+/// `secp256k1_blake160_sighash_all` lock in the genesis block.
+///
+/// The script is the output#1 of the cellbase on the standard chains, otherwise all outputs in
+/// the genesis block are scanned by the data hash. The cell dep is the dep group which
+/// contains the script.
+pub fn secp256k1_code_hash_and_dep(
+    genesis: &core::BlockView,
+) -> Result<(fixed::H256, packed::CellDep)> {
+    secp256k1_code_hash_and_dep_with(genesis, &SECP256K1_BLAKE160_SIGHASH_ALL_DATA_HASH.pack())
+}
+
+fn secp256k1_code_hash_and_dep_with(
+    genesis: &core::BlockView,
+    data_hash: &packed::Byte32,
+) -> Result<(fixed::H256, packed::CellDep)> {
+    let (out_point, output) = find_secp256k1(genesis, data_hash)?;
+    let code_hash = output
+        .type_()
+        .to_opt()
+        .map(|script| script.calc_script_hash().unpack())
+        .ok_or_else(|| Error::invalid_argument("the secp256k1 script has no type script"))?;
+    let cell_dep = find_dep_group(genesis, &out_point).ok_or_else(|| {
+        Error::invalid_argument("no dep group contains the secp256k1 script in the genesis block")
+    })?;
+    Ok((code_hash, cell_dep))
+}
+
+/// Finds the code hash (hash type is `type`) and the cell dep of the
+/// `secp256k1_blake160_multisig_all` lock in the genesis block.
+///
+/// The multisig script shares the secp256k1 data with the sighash script, so it's the other
+/// member of the dep group which contains the secp256k1 data but not the sighash script. The
+/// cell dep is that dep group.
+pub fn multisig_code_hash_and_dep(
+    genesis: &core::BlockView,
+) -> Result<(fixed::H256, packed::CellDep)> {
+    multisig_code_hash_and_dep_with(genesis, &SECP256K1_BLAKE160_SIGHASH_ALL_DATA_HASH.pack())
+}
+
+fn multisig_code_hash_and_dep_with(
+    genesis: &core::BlockView,
+    sighash_data_hash: &packed::Byte32,
+) -> Result<(fixed::H256, packed::CellDep)> {
+    let (sighash, _) = find_secp256k1(genesis, sighash_data_hash)?;
+    let groups = dep_groups(genesis);
+    // the members which are shared with the sighash script, such as the secp256k1 data
+    let shared = groups
+        .iter()
+        .find(|(_, members)| members.contains(&sighash))
+        .map(|(_, members)| {
+            members
+                .iter()
+                .filter(|member| **member != sighash)
+                .collect::<Vec<_>>()
+        })
+        .ok_or_else(|| {
+            Error::invalid_argument(
+                "no dep group contains the secp256k1 script in the genesis block",
+            )
+        })?;
+    let (group, out_point) = groups
+        .iter()
+        .filter(|(_, members)| {
+            !members.contains(&sighash) && shared.iter().all(|member| members.contains(member))
+        })
+        .find_map(|(group, members)| {
+            let mut others = members.iter().filter(|member| !shared.contains(member));
+            match (others.next(), others.next()) {
+                (Some(out_point), None) => Some((group, out_point)),
+                _ => None,
+            }
+        })
+        .ok_or_else(|| Error::invalid_argument("no multisig script in the genesis block"))?;
+    let code_hash = genesis_output(genesis, out_point)
+        .and_then(|output| output.type_().to_opt())
+        .map(|script| script.calc_script_hash().unpack())
+        .ok_or_else(|| Error::invalid_argument("the multisig script has no type script"))?;
+    let cell_dep = packed::CellDep::new_builder()
+        .out_point(group.clone())
+        .dep_type(core::DepType::DepGroup.into())
+        .build();
+    Ok((code_hash, cell_dep))
+}
+
+// Finds the sighash script by its data hash, it's the output#1 of the cellbase on the
+// standard chains.
+fn find_secp256k1(
+    genesis: &core::BlockView,
+    data_hash: &packed::Byte32,
+) -> Result<(packed::OutPoint, packed::CellOutput)> {
+    let is_secp256k1 = |data: &[u8]| &packed::CellOutput::calc_data_hash(data) == data_hash;
+    let transactions = genesis.transactions();
+    let standard = transactions.first().and_then(|tx| {
+        tx.output_with_data(1)
+            .filter(|(_, data)| is_secp256k1(data))
+            .map(|(output, _)| (tx.hash(), 1, output))
+    });
+    let (tx_hash, index, output) = standard
+        .or_else(|| {
+            transactions.iter().find_map(|tx| {
+                tx.outputs_with_data_iter()
+                    .enumerate()
+                    .find(|(_, (_, data))| is_secp256k1(data))
+                    .map(|(index, (output, _))| (tx.hash(), index, output))
+            })
+        })
+        .ok_or_else(|| Error::invalid_argument("no secp256k1 script in the genesis block"))?;
+    Ok((packed::OutPoint::new(tx_hash, index as u32), output))
+}
+
+// Finds the dep group which contains the out point in the genesis block.
+fn find_dep_group(
+    genesis: &core::BlockView,
+    out_point: &packed::OutPoint,
+) -> Option<packed::CellDep> {
+    dep_groups(genesis)
+        .into_iter()
+        .find(|(_, members)| members.contains(out_point))
+        .map(|(group, _)| {
+            packed::CellDep::new_builder()
+                .out_point(group)
+                .dep_type(core::DepType::DepGroup.into())
+                .build()
+        })
+}
+
+// The dep groups in the genesis block, whose data are out points of the genesis block.
+fn dep_groups(genesis: &core::BlockView) -> Vec<(packed::OutPoint, Vec<packed::OutPoint>)> {
+    genesis
+        .transactions()
+        .iter()
+        .flat_map(|tx| {
+            let tx_hash = tx.hash();
+            tx.outputs_data()
+                .into_iter()
+                .enumerate()
+                .map(move |(index, data)| {
+                    (packed::OutPoint::new(tx_hash.clone(), index as u32), data)
+                })
+        })
+        .filter_map(|(group, data)| {
+            let members = packed::OutPointVec::from_slice(&data.raw_data()).ok()?;
+            let members = members.into_iter().collect::<Vec<_>>();
+            let is_group = !members.is_empty()
+                && members
+                    .iter()
+                    .all(|member| genesis_output(genesis, member).is_some());
+            if is_group {
+                Some((group, members))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn genesis_output(
+    genesis: &core::BlockView,
+    out_point: &packed::OutPoint,
+) -> Option<packed::CellOutput> {
+    let tx_hash = out_point.tx_hash();
+    let index: u32 = out_point.index().unpack();
+    genesis
+        .transactions()
+        .iter()
+        .find(|tx| tx.hash() == tx_hash)
+        .and_then(|tx| tx.output(index as usize))
+}
+
+/// Finds the code hash (hash type is `type`) and the cell dep of the DAO type script in the
+/// genesis block.
+///
+/// All outputs in the genesis block are scanned by the data hash. If the binary is not the
+/// known one, the output whose type script has the standard DAO type hash is used, which is
+/// the output#2 of the cellbase deployed with type id.
+pub fn dao_code_hash_and_dep(genesis: &core::BlockView) -> Result<(fixed::H256, packed::CellDep)> {
+    dao_code_hash_and_dep_with(genesis, &DAO_DATA_HASH.pack())
+}
+
+fn dao_code_hash_and_dep_with(
+    genesis: &core::BlockView,
+    data_hash: &packed::Byte32,
+) -> Result<(fixed::H256, packed::CellDep)> {
+    let outputs = genesis
+        .transactions()
+        .into_iter()
+        .flat_map(|tx| {
+            let tx_hash = tx.hash();
+            tx.outputs_with_data_iter()
+                .enumerate()
+                .map(move |(index, (output, data))| {
+                    let out_point = packed::OutPoint::new(tx_hash.clone(), index as u32);
+                    (out_point, output, data)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let by_data_hash = outputs
+        .iter()
+        .find(|(_, _, data)| &packed::CellOutput::calc_data_hash(data) == data_hash);
+    let by_type_hash = || {
+        let type_hash = DAO_TYPE_HASH.pack();
+        outputs.iter().find(|(_, output, _)| {
+            output
+                .type_()
+                .to_opt()
+                .map(|script| script.calc_script_hash() == type_hash)
+                .unwrap_or(false)
+        })
+    };
+    let (out_point, output, _) = by_data_hash
+        .or_else(by_type_hash)
+        .ok_or_else(|| Error::invalid_argument("no dao script in the genesis block"))?;
+    let code_hash = output
+        .type_()
+        .to_opt()
+        .map(|script| script.calc_script_hash().unpack())
+        .ok_or_else(|| Error::invalid_argument("the dao script has no type script"))?;
+    let cell_dep = packed::CellDep::new_builder()
+        .out_point(out_point.clone())
+        .dep_type(core::DepType::Code.into())
+        .build();
+    Ok((code_hash, cell_dep))
+}
+
+/// Describes what an outputs validator of `send_transaction` accepts.
+pub trait OutputsValidatorExt {
+    fn description(&self) -> &'static str;
+}
+
+impl OutputsValidatorExt for rpc::OutputsValidator {
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Default => {
+                "only accepts outputs whose lock is secp256k1_blake160_sighash_all (20 bytes args) \
+                 or secp256k1_blake160_multisig_all (20 or 28 bytes args) in the genesis block, \
+                 and whose type is empty or the DAO, all scripts are referenced by type hash"
+            }
+            Self::Passthrough => "accepts any outputs",
+        }
+    }
+}
+
+/// Checks the outputs of a transaction locally, the same as the outputs validator in the node.
+pub fn validate_outputs_against(
+    tx: &packed::Transaction,
+    validator: &rpc::OutputsValidator,
+    genesis_info: &GenesisInfo,
+) -> Result<()> {
+    if let rpc::OutputsValidator::Passthrough = validator {
+        return Ok(());
+    }
+    let type_hash_type: packed::Byte = core::ScriptHashType::Type.into();
+    let is_system = |script: &packed::Script, code_hash: &fixed::H256| {
+        script.hash_type() == type_hash_type && script.code_hash() == code_hash.pack()
+    };
+    for (index, output) in tx.raw().outputs().into_iter().enumerate() {
+        let lock = output.lock();
+        let args_len = lock.args().raw_data().len();
+        let is_valid_lock = (is_system(&lock, &genesis_info.secp256k1_code_hash) && args_len == 20)
+            || (is_system(&lock, &genesis_info.multisig_code_hash)
+                && (args_len == 20 || args_len == 28));
+        if !is_valid_lock {
+            return Err(Error::invalid_argument(format!(
+                "output#{}: the lock is not a well-known script (the validator {})",
+                index,
+                validator.description()
+            )));
+        }
+        if let Some(type_) = output.type_().to_opt() {
+            if !is_system(&type_, &genesis_info.dao_code_hash) {
+                return Err(Error::invalid_argument(format!(
+                    "output#{}: the type is not the DAO (the validator {})",
+                    index,
+                    validator.description()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether a script matches the code hash and the hash type, and whether its args start
+/// with the prefix, which is the same as the prefix search of the indexer.
+///
+/// The args are not checked if no prefix is given.
+pub fn script_matches(
+    candidate: &packed::Script,
+    code_hash: &packed::Byte32,
+    hash_type: core::ScriptHashType,
+    args_prefix: Option<&[u8]>,
+) -> bool {
+    let hash_type: u8 = hash_type.into();
+    if &candidate.code_hash() != code_hash || u8::from(candidate.hash_type()) != hash_type {
+        return false;
+    }
+    if let Some(prefix) = args_prefix {
+        candidate.args().raw_data().starts_with(prefix)
+    } else {
+        true
+    }
+}
+
+pub fn header_hash(header: &packed::Header) -> fixed::H256 {
+    header.calc_header_hash().unpack()
+}
+
+/// Checks the transactions root, the proposals hash and the uncles hash in the header against
+/// the block body.
+///
+/// The block view should be created by `into_view_without_reset_header`, otherwise the header
+/// is always consistent with the body. The block hash is calculated from the header, so it
+/// should be checked by the caller.
+pub fn verify_block(block: &core::BlockView) -> Result<()> {
+    let header = block.header();
+    let raw_transactions_root = merkle_root(block.tx_hashes());
+    let witnesses_root = merkle_root(block.tx_witness_hashes());
+    let transactions_root = merkle_root(&[raw_transactions_root, witnesses_root]);
+    let checks = [
+        (
+            "transactions root",
+            header.transactions_root(),
+            transactions_root,
+        ),
+        (
+            "proposals hash",
+            header.proposals_hash(),
+            block.calc_proposals_hash(),
+        ),
+        (
+            "uncles hash",
+            header.uncles_hash(),
+            block.calc_uncles_hash(),
+        ),
+    ];
+    for (name, expected, actual) in checks.iter() {
+        if expected != actual {
+            return Err(Error::corruption(format!(
+                "the {} of block {:#x} is {:#x}, but the body is {:#x}",
+                name,
+                block.hash(),
+                expected,
+                actual
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the headers are a chain, each header is the parent of the next one.
+///
+/// The error reports the index of the first header which doesn't link to the previous one.
+pub fn verify_header_chain(headers: &[core::HeaderView]) -> Result<()> {
+    for (index, pair) in headers.windows(2).enumerate() {
+        let (parent, header) = (&pair[0], &pair[1]);
+        if header.parent_hash() != parent.hash() {
+            return Err(Error::corruption(format!(
+                "header[{}]: the parent hash is {:#x}, but the previous header is {:#x}",
+                index + 1,
+                header.parent_hash(),
+                parent.hash()
+            )));
+        }
+        if header.number() != parent.number() + 1 {
+            return Err(Error::corruption(format!(
+                "header[{}]: the number is {}, but the previous header is {}",
+                index + 1,
+                header.number(),
+                parent.number()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Verifies a proof of the transactions against a trusted header, without trusting the node.
+///
+/// The transactions are proved to be included in the block of the header if the proof is for
+/// that block and the proof leads to the transactions root in the header.
+pub fn verify_transaction_proof(
+    proof: &rpc::TransactionProof,
+    tx_hashes: &[fixed::H256],
+    header: &core::HeaderView,
+) -> bool {
+    if proof.block_hash.pack() != header.hash() {
+        return false;
+    }
+    let indices = proof
+        .proof
+        .indices
+        .iter()
+        .map(|index| index.value())
+        .collect();
+    let lemmas = proof
+        .proof
+        .lemmas
+        .iter()
+        .map(|lemma| lemma.pack())
+        .collect();
+    let leaves = tx_hashes.iter().map(|hash| hash.pack()).collect::<Vec<_>>();
+    MerkleProof::new(indices, lemmas)
+        .root(&leaves)
+        .map(|raw_transactions_root| {
+            let witnesses_root = proof.witnesses_root.pack();
+            merkle_root(&[raw_transactions_root, witnesses_root]) == header.transactions_root()
+        })
+        .unwrap_or(false)
+}
+
+/// Checks whether a hash is all zeros, such as the parent hash of the genesis block or the
+/// transaction hash of the null out point.
+pub fn is_zero(hash: &fixed::H256) -> bool {
+    hash == &ZERO_H256
+}
+
+/// Checks whether an input is the input of a cellbase transaction.
+///
+/// The input of a cellbase transaction refers to the null out point: a zero transaction hash
+/// with the max index.
+pub fn is_cellbase_input(input: &packed::CellInput) -> bool {
+    input.previous_output().is_null()
+}
+
+#[cfg(test)]
+mod tests {
+    use uckb_jsonrpc_core::{
+        secp256k1::Signature,
+        types::{
+            fixed::{h160, h256, H160, H256},
+            utilities::CBMT,
+        },
+    };
+
+    use super::*;
+
+    const TYPE_ID_CODE_HASH: fixed::H256 =
+        h256!("0x00000000000000000000000000000000000000000000000000545950455f4944");
+
+    fn type_id_script(type_id: [u8; 32]) -> packed::Script {
+        packed::Script::new_builder()
+            .code_hash(TYPE_ID_CODE_HASH.pack())
+            .hash_type(core::ScriptHashType::Type.into())
+            .args(Bytes::from(type_id.to_vec()).pack())
+            .build()
+    }
+
+    #[test]
+    fn type_id_hashes_input_and_index() {
+        let input = packed::CellInput::new_cellbase_input(0);
+        let mut data = input.as_slice().to_vec();
+        data.extend_from_slice(&1u64.to_le_bytes());
+        let expected = blake2b_256_with_personal(&data, DEFAULT_PERSONAL).unwrap();
+        assert_eq!(calculate_type_id(&input, 1), expected);
+        let type_id: fixed::H256 = calculate_type_id(&input, 1).pack().unpack();
+        assert_eq!(
+            type_id,
+            h256!("0x8536c9d5d908bd89fc70099e4284870708b6632356aad98734fcf43f6f71c304")
+        );
+    }
+
+    // The system scripts in the genesis block of the mainnet are deployed with type id, the
+    // first input is the input of the genesis cellbase.
+    #[test]
+    fn type_id_of_mainnet_system_scripts() {
+        let input = packed::CellInput::new_cellbase_input(0);
+        let code_hashes = [
+            (
+                1,
+                h256!("0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8"),
+            ),
+            (
+                2,
+                h256!("0x82d76d1b75fe2fd9a27dfbaa65a039221a380d76c926f378d3f81cf3e7e13f2e"),
+            ),
+            (
+                4,
+                h256!("0x5c5069eb0857efc65e1bca0c07df34c31663b3622fd3876c876320fc9634e2a8"),
+            ),
+        ];
+        for (index, code_hash) in code_hashes.iter() {
+            let script = type_id_script(calculate_type_id(&input, *index));
+            let script_hash: fixed::H256 = script.calc_script_hash().unpack();
+            assert_eq!(&script_hash, code_hash, "output#{}", index);
+        }
+        let other = type_id_script(calculate_type_id(&input, 3));
+        let other_hash: fixed::H256 = other.calc_script_hash().unpack();
+        assert_ne!(&other_hash, &code_hashes[0].1);
+    }
+
+    fn secp256k1_lock() -> packed::Script {
+        packed::Script::new_builder()
+            .code_hash(
+                h256!("0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8").pack(),
+            )
+            .hash_type(core::ScriptHashType::Type.into())
+            .args(Bytes::from(vec![0u8; 20]).pack())
+            .build()
+    }
+
+    #[test]
+    fn occupied_capacity_of_secp256k1_cell() {
+        let output = packed::CellOutput::new_builder()
+            .lock(secp256k1_lock())
+            .build();
+        let capacity = occupied_capacity(&output, 0).unwrap();
+        assert_eq!(capacity, core::Capacity::bytes(61).unwrap());
+        assert_eq!(capacity.as_u64(), 61_0000_0000);
+    }
+
+    // A DAO deposit has a type script without args and 8 bytes data.
+    #[test]
+    fn occupied_capacity_of_dao_deposit() {
+        let dao = packed::Script::new_builder()
+            .code_hash(
+                h256!("0x82d76d1b75fe2fd9a27dfbaa65a039221a380d76c926f378d3f81cf3e7e13f2e").pack(),
+            )
+            .hash_type(core::ScriptHashType::Type.into())
+            .build();
+        let output = packed::CellOutput::new_builder()
+            .lock(secp256k1_lock())
+            .type_(Some(dao).pack())
+            .build();
+        let capacity = occupied_capacity(&output, 8).unwrap();
+        assert_eq!(capacity, core::Capacity::bytes(102).unwrap());
+    }
+
+    #[test]
+    fn occupied_capacity_overflow() {
+        let output = packed::CellOutput::new_builder()
+            .lock(secp256k1_lock())
+            .build();
+        assert!(occupied_capacity(&output, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn zero_hash() {
+        assert!(is_zero(&ZERO_H256));
+        assert!(is_zero(&fixed::H256::default()));
+        let mut hash = ZERO_H256;
+        hash.0[31] = 1;
+        assert!(!is_zero(&hash));
+    }
+
+    #[test]
+    fn cellbase_input() {
+        let cellbase = packed::CellInput::new_cellbase_input(10);
+        assert!(is_cellbase_input(&cellbase));
+        let zero_index = packed::OutPoint::new(ZERO_H256.pack(), 0);
+        assert!(!is_cellbase_input(&packed::CellInput::new(zero_index, 0)));
+        let mut hash = ZERO_H256;
+        hash.0[0] = 1;
+        let max_index = packed::OutPoint::new(hash.pack(), u32::MAX);
+        assert!(!is_cellbase_input(&packed::CellInput::new(max_index, 0)));
+    }
+
+    fn transfer(change_capacity: core::Capacity) -> packed::Transaction {
+        let output = |capacity: core::Capacity| {
+            packed::CellOutput::new_builder()
+                .capacity(capacity.pack())
+                .lock(secp256k1_lock())
+                .build()
+        };
+        let raw = packed::RawTransaction::new_builder()
+            .inputs(
+                packed::CellInputVec::new_builder()
+                    .push(packed::CellInput::new(packed::OutPoint::default(), 0))
+                    .build(),
+            )
+            .outputs(
+                packed::CellOutputVec::new_builder()
+                    .push(output(core::Capacity::bytes(100).unwrap()))
+                    .push(output(change_capacity))
+                    .build(),
+            )
+            .outputs_data(vec![Bytes::new().pack(); 2].pack())
+            .build();
+        packed::Transaction::new_builder()
+            .raw(raw)
+            .witnesses(vec![Bytes::from(vec![0u8; 85]).pack()].pack())
+            .build()
+    }
+
+    fn capacity_of(tx: &packed::Transaction, index: usize) -> core::Capacity {
+        tx.raw().outputs().get(index).unwrap().capacity().unpack()
+    }
+
+    #[test]
+    fn adjust_fee_to_rate_is_idempotent() {
+        let inputs_capacity = core::Capacity::bytes(1000).unwrap();
+        let fee_rate = core::FeeRate::from_u64(1000);
+        let mut tx = transfer(core::Capacity::bytes(900).unwrap());
+        let fee = adjust_fee_to_rate(&mut tx, inputs_capacity, 1, fee_rate).unwrap();
+        assert_eq!(fee, fee_rate.fee(tx.serialized_size_in_block()));
+        let change = core::Capacity::bytes(900).unwrap().safe_sub(fee).unwrap();
+        assert_eq!(capacity_of(&tx, 1), change);
+        let adjusted = tx.clone();
+        assert_eq!(
+            adjust_fee_to_rate(&mut tx, inputs_capacity, 1, fee_rate).unwrap(),
+            fee
+        );
+        assert_eq!(tx.as_slice(), adjusted.as_slice());
+    }
+
+    // A lower fee rate returns the overpaid fee to the change output.
+    #[test]
+    fn adjust_fee_to_rate_only_takes_the_difference() {
+        let inputs_capacity = core::Capacity::bytes(1000).unwrap();
+        let mut tx = transfer(core::Capacity::bytes(800).unwrap());
+        let fee_rate = core::FeeRate::from_u64(2000);
+        let fee = adjust_fee_to_rate(&mut tx, inputs_capacity, 1, fee_rate).unwrap();
+        let change = core::Capacity::bytes(900).unwrap().safe_sub(fee).unwrap();
+        assert_eq!(capacity_of(&tx, 1), change);
+        assert_eq!(capacity_of(&tx, 0), core::Capacity::bytes(100).unwrap());
+    }
+
+    #[test]
+    fn adjust_fee_to_rate_rejects_insufficient_capacity() {
+        let fee_rate = core::FeeRate::from_u64(1000);
+        let mut tx = transfer(core::Capacity::bytes(900).unwrap());
+        let inputs_capacity = core::Capacity::bytes(999).unwrap();
+        assert!(adjust_fee_to_rate(&mut tx, inputs_capacity, 1, fee_rate).is_err());
+        let inputs_capacity = core::Capacity::bytes(161).unwrap();
+        let mut tx = transfer(core::Capacity::bytes(61).unwrap());
+        assert!(adjust_fee_to_rate(&mut tx, inputs_capacity, 1, fee_rate).is_err());
+        assert!(adjust_fee_to_rate(&mut tx, inputs_capacity, 2, fee_rate).is_err());
+    }
+
+    const SIGHASH: &[u8] = b"sighash";
+    const SECP256K1_DATA: &[u8] = b"secp256k1 data";
+    const MULTISIG: &[u8] = b"multisig";
+    const DAO: &[u8] = b"dao";
+
+    // Builds a genesis block like the chain spec of CKB: the binaries are deployed with type
+    // id in the cellbase from output#1, and the dep groups are in the second transaction.
+    fn genesis(binaries: &[&[u8]], groups: &[&[&[u8]]]) -> core::BlockView {
+        let input = packed::CellInput::new_cellbase_input(0);
+        let mut cellbase = core::TransactionBuilder::default()
+            .input(input.clone())
+            .output(Default::default())
+            .output_data(Default::default());
+        for (index, binary) in binaries.iter().enumerate() {
+            let type_id = calculate_type_id(&input, index as u64 + 1);
+            let output = packed::CellOutput::new_builder()
+                .type_(Some(type_id_script(type_id)).pack())
+                .build();
+            cellbase = cellbase
+                .output(output)
+                .output_data(Bytes::from(binary.to_vec()).pack());
+        }
+        let cellbase = cellbase.build();
+        let mut dep_groups = core::TransactionBuilder::default();
+        for members in groups {
+            let out_points = members
+                .iter()
+                .map(|member| {
+                    let index = binaries.iter().position(|binary| binary == member).unwrap();
+                    packed::OutPoint::new(cellbase.hash(), index as u32 + 1)
+                })
+                .collect::<Vec<_>>();
+            let data = packed::OutPointVec::new_builder().set(out_points).build();
+            dep_groups = dep_groups
+                .output(Default::default())
+                .output_data(data.as_bytes().pack());
+        }
+        core::BlockBuilder::default()
+            .transaction(cellbase)
+            .transaction(dep_groups.build())
+            .build()
+    }
+
+    fn data_hash(data: &[u8]) -> packed::Byte32 {
+        packed::CellOutput::calc_data_hash(data)
+    }
+
+    fn dep_group_index(cell_dep: &packed::CellDep) -> u32 {
+        assert_eq!(cell_dep.dep_type(), core::DepType::DepGroup.into());
+        cell_dep.out_point().index().unpack()
+    }
+
+    #[test]
+    fn system_scripts_of_the_standard_genesis() {
+        let genesis = genesis(
+            &[SIGHASH, DAO, SECP256K1_DATA, MULTISIG],
+            &[&[SECP256K1_DATA, SIGHASH], &[SECP256K1_DATA, MULTISIG]],
+        );
+        let dep_groups_hash = genesis.transactions()[1].hash();
+        let (code_hash, cell_dep) =
+            secp256k1_code_hash_and_dep_with(&genesis, &data_hash(SIGHASH)).unwrap();
+        assert_eq!(
+            code_hash,
+            h256!("0x9bd7e06f3ecf4be0f2fcd2188b23f1b9fcc88e5d4b65a8637b17723bbda3cce8")
+        );
+        assert_eq!(cell_dep.out_point().tx_hash(), dep_groups_hash);
+        assert_eq!(dep_group_index(&cell_dep), 0);
+        let (code_hash, cell_dep) =
+            multisig_code_hash_and_dep_with(&genesis, &data_hash(SIGHASH)).unwrap();
+        assert_eq!(
+            code_hash,
+            h256!("0x5c5069eb0857efc65e1bca0c07df34c31663b3622fd3876c876320fc9634e2a8")
+        );
+        assert_eq!(cell_dep.out_point().tx_hash(), dep_groups_hash);
+        assert_eq!(dep_group_index(&cell_dep), 1);
+    }
+
+    #[test]
+    fn multisig_is_found_by_the_dep_group() {
+        let genesis = genesis(
+            &[DAO, MULTISIG, SECP256K1_DATA, SIGHASH],
+            &[&[SECP256K1_DATA, MULTISIG], &[SECP256K1_DATA, SIGHASH]],
+        );
+        let (code_hash, cell_dep) =
+            multisig_code_hash_and_dep_with(&genesis, &data_hash(SIGHASH)).unwrap();
+        let input = packed::CellInput::new_cellbase_input(0);
+        let expected: fixed::H256 = type_id_script(calculate_type_id(&input, 2))
+            .calc_script_hash()
+            .unpack();
+        assert_eq!(code_hash, expected);
+        assert_eq!(dep_group_index(&cell_dep), 0);
+    }
+
+    #[test]
+    fn multisig_is_required() {
+        let genesis = genesis(
+            &[SIGHASH, DAO, SECP256K1_DATA, MULTISIG],
+            &[&[SECP256K1_DATA, SIGHASH]],
+        );
+        assert!(secp256k1_code_hash_and_dep_with(&genesis, &data_hash(SIGHASH)).is_ok());
+        let result = multisig_code_hash_and_dep_with(&genesis, &data_hash(SIGHASH));
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        let result = multisig_code_hash_and_dep_with(&genesis, &data_hash(MULTISIG));
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    fn with_cell_dep(tx: packed::Transaction) -> packed::Transaction {
+        let raw = tx
+            .raw()
+            .as_builder()
+            .cell_deps(vec![packed::CellDep::default()].pack())
+            .build();
+        tx.as_builder().raw(raw).build()
+    }
+
+    #[test]
+    fn valid_transaction_structure() {
+        let tx = with_cell_dep(transfer(core::Capacity::bytes(61).unwrap()));
+        validate_transaction_structure(&tx).unwrap();
+        let tx = transfer(core::Capacity::bytes(61).unwrap());
+        assert!(validate_transaction_structure(&tx).is_err());
+    }
+
+    #[test]
+    fn outputs_and_outputs_data_mismatch() {
+        let tx = with_cell_dep(transfer(core::Capacity::bytes(61).unwrap()));
+        let raw = tx
+            .raw()
+            .as_builder()
+            .outputs_data(vec![Bytes::new().pack()].pack())
+            .build();
+        let tx = tx.as_builder().raw(raw).build();
+        let result = validate_transaction_structure(&tx);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn output_without_enough_capacity() {
+        let capacity = core::Capacity::bytes(61).unwrap().safe_sub(1u64).unwrap();
+        let tx = with_cell_dep(transfer(capacity));
+        let result = validate_transaction_structure(&tx);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    // The first account in the genesis block of a dev chain.
+    #[test]
+    fn lock_arg_is_the_blake160_of_the_pubkey() {
+        let privkey = Privkey::from(h256!(
+            "0xd00c06bfd800d27397002dca6fb0993d5ba6399b4238b2f29ee9deb97593d2bc"
+        ));
+        let pubkey = privkey.pubkey().unwrap();
+        let lock_arg = pubkey_to_lock_arg(&pubkey);
+        assert_eq!(lock_arg.raw_data().len(), 20);
+        assert_eq!(
+            lock_arg.raw_data().as_ref(),
+            &blake2b_256_with_personal(&pubkey.serialize(), DEFAULT_PERSONAL).unwrap()[..20]
+        );
+        let expected = h160!("0xc8328aabcd9b9e8e64fbc566c4385c3bdeb219d7");
+        assert_eq!(lock_arg.raw_data().as_ref(), expected.as_bytes());
+    }
+
+    // The expected hashes are calculated independently from the molecule layout.
+    #[test]
+    fn sighash_all_message() {
+        let inputs = (0..3u8)
+            .map(|index| {
+                let out_point = packed::OutPoint::new([index + 1; 32].pack(), u32::from(index));
+                packed::CellInput::new(out_point, 0)
+            })
+            .collect::<Vec<_>>();
+        let raw = packed::RawTransaction::new_builder()
+            .inputs(inputs.pack())
+            .build();
+        let witnesses = [&b""[..], b"other group", b"\x01\x02", b"extra"]
+            .iter()
+            .map(|witness| witness.pack())
+            .collect::<Vec<_>>();
+        let tx = packed::Transaction::new_builder()
+            .raw(raw)
+            .witnesses(witnesses.pack())
+            .build();
+        let tx_hash: fixed::H256 = tx.calc_tx_hash().unpack();
+        assert_eq!(
+            tx_hash,
+            h256!("0x8d30067d0c125fc3edbfda8eb836d1474eb71a6347cf888284915790b2c3448e")
+        );
+        let witnesses = tx
+            .witnesses()
+            .into_iter()
+            .map(|witness| witness.raw_data())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            raw_signing_message(&tx, &[0, 2], &witnesses).unwrap(),
+            h256!("0x0939f77089d43d75282a2040ded9a1bb9b87c8cbbf7bcb0776e377b8dd2d05b2")
+        );
+        assert!(raw_signing_message(&tx, &[], &witnesses).is_err());
+        assert!(raw_signing_message(&tx, &[3], &witnesses).is_err());
+    }
+
+    // The message is calculated over the given witnesses, not the witnesses in the transaction.
+    #[test]
+    fn sighash_all_message_of_placeholder_witnesses() {
+        let inputs = (0..2u8)
+            .map(|index| {
+                let out_point = packed::OutPoint::new([index + 1; 32].pack(), u32::from(index));
+                packed::CellInput::new(out_point, 0)
+            })
+            .collect::<Vec<_>>();
+        let raw = packed::RawTransaction::new_builder()
+            .inputs(inputs.pack())
+            .build();
+        let tx = packed::Transaction::new_builder().raw(raw).build();
+        let input_type = Some(Bytes::from(&b"input type"[..])).pack();
+        let witnesses = vec![
+            packed::WitnessArgs::new_builder()
+                .input_type(input_type)
+                .build(),
+            packed::WitnessArgs::default(),
+        ];
+        let raw_witnesses = witnesses
+            .iter()
+            .map(|witness| witness.as_bytes())
+            .collect::<Vec<_>>();
+        let expected = raw_signing_message(&tx, &[0, 1], &raw_witnesses).unwrap();
+        assert_eq!(
+            tx_signing_message(&tx, &[0, 1], &witnesses).unwrap(),
+            expected
+        );
+        // The lock of the first witness doesn't change the message.
+        let signed = witnesses[0]
+            .clone()
+            .as_builder()
+            .lock(Some(Bytes::from(vec![1u8; SECP256K1_SIGNATURE_SIZE])).pack())
+            .build();
+        let signed_witnesses = vec![signed, witnesses[1].clone()];
+        assert_eq!(
+            tx_signing_message(&tx, &[0, 1], &signed_witnesses).unwrap(),
+            expected
+        );
+        assert!(tx_signing_message(&tx, &[0, 1], &[]).is_err());
+    }
+
+    #[test]
+    fn reserve_witness_space_puts_lock_placeholders() {
+        let output_type = Some(Bytes::from(&b"output type"[..])).pack();
+        let existing = packed::WitnessArgs::new_builder()
+            .output_type(output_type.clone())
+            .build();
+        let mut witnesses = two_groups()
+            .build()
+            .witnesses()
+            .into_iter()
+            .collect::<Vec<_>>();
+        witnesses[2] = existing.as_bytes().pack();
+        let mut tx = two_groups().set_witnesses(witnesses).build().data();
+        reserve_witness_space(&mut tx, &[2, 1]).unwrap();
+        let witnesses = tx.witnesses();
+        assert_eq!(witnesses.len(), 3);
+        let placeholder = [0u8; SECP256K1_SIGNATURE_SIZE];
+        for index in &[0, 2] {
+            let witness_args =
+                packed::WitnessArgs::from_slice(&witnesses.get(*index).unwrap().raw_data())
+                    .unwrap();
+            let lock = witness_args.lock().to_opt().unwrap().raw_data();
+            assert_eq!(lock.as_ref(), &placeholder[..]);
+        }
+        assert!(witnesses.get(1).unwrap().is_empty());
+        let first = packed::WitnessArgs::from_slice(&witnesses.get(0).unwrap().raw_data()).unwrap();
+        assert_eq!(
+            first.input_type().to_opt().unwrap().raw_data().as_ref(),
+            b"input type"
+        );
+        let last = packed::WitnessArgs::from_slice(&witnesses.get(2).unwrap().raw_data()).unwrap();
+        assert_eq!(last.output_type().as_slice(), output_type.as_slice());
+    }
+
+    #[test]
+    fn reserve_witness_space_rejects_mismatched_groups() {
+        let mut tx = two_groups().build().data();
+        let witnesses = tx.witnesses();
+        for group_sizes in &[&[2, 2][..], &[1, 1], &[]] {
+            let result = reserve_witness_space(&mut tx, group_sizes);
+            assert!(matches!(result, Err(Error::InvalidArgument(_))));
+        }
+        assert_eq!(tx.witnesses().as_slice(), witnesses.as_slice());
+    }
+
+    fn sign_with_privkey(privkey: &Privkey, message: &fixed::H256) -> Result<[u8; 65]> {
+        let message = Message::from_slice(message.as_bytes()).unwrap();
+        let signature = privkey.sign_recoverable(&message).unwrap().serialize();
+        let mut bytes = [0u8; SECP256K1_SIGNATURE_SIZE];
+        bytes.copy_from_slice(&signature);
+        Ok(bytes)
+    }
+
+    // Two lock groups: input#0 and input#1 are marked by the witness of input#0, input#2 by
+    // its own witness.
+    fn two_groups() -> core::TransactionBuilder {
+        let inputs = (0..3u8)
+            .map(|index| {
+                let out_point = packed::OutPoint::new([index + 1; 32].pack(), u32::from(index));
+                packed::CellInput::new(out_point, 0)
+            })
+            .collect::<Vec<_>>();
+        let input_type = Some(Bytes::from(&b"input type"[..])).pack();
+        let first = packed::WitnessArgs::new_builder()
+            .input_type(input_type)
+            .build();
+        core::TransactionBuilder::default()
+            .inputs(inputs)
+            .witness(first.as_bytes().pack())
+            .witness(Bytes::new().pack())
+            .witness(packed::WitnessArgs::default().as_bytes().pack())
+    }
+
+    #[test]
+    fn sign_with_puts_signatures_at_the_first_input_of_each_group() {
+        let privkey = Privkey::from(h256!(
+            "0xd00c06bfd800d27397002dca6fb0993d5ba6399b4238b2f29ee9deb97593d2bc"
+        ));
+        let tx = two_groups()
+            .sign_with(|message| sign_with_privkey(&privkey, message))
+            .unwrap();
+        let witnesses = tx
+            .witnesses()
+            .into_iter()
+            .map(|witness| witness.raw_data())
+            .collect::<Vec<_>>();
+        assert_eq!(witnesses.len(), 3);
+        assert!(witnesses[1].is_empty());
+        for group in &[vec![0, 1], vec![2]] {
+            let witness_args = packed::WitnessArgs::from_slice(&witnesses[group[0]]).unwrap();
+            let lock = witness_args.lock().to_opt().unwrap().raw_data();
+            assert_eq!(lock.len(), SECP256K1_SIGNATURE_SIZE);
+            let message = raw_signing_message(&tx, group, &witnesses).unwrap();
+            let message = Message::from_slice(message.as_bytes()).unwrap();
+            let signature = Signature::from_slice(&lock).unwrap();
+            assert_eq!(
+                signature.recover(&message).unwrap(),
+                privkey.pubkey().unwrap()
+            );
+        }
+        let witness_args = packed::WitnessArgs::from_slice(&witnesses[0]).unwrap();
+        assert_eq!(
+            witness_args
+                .input_type()
+                .to_opt()
+                .unwrap()
+                .raw_data()
+                .as_ref(),
+            b"input type"
+        );
+    }
+
+    #[test]
+    fn sign_with_propagates_the_signer_error() {
+        let result = two_groups().sign_with(|_| Err(Error::invalid_argument("rejected")));
+        assert!(matches!(result, Err(Error::InvalidArgument(ref msg)) if msg == "rejected"));
+        let result = two_groups().sign_with(|_| Ok([0u8; SECP256K1_SIGNATURE_SIZE]));
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn size_and_weight_of_transactions() {
+        // the header and the fields of the empty raw transaction and witnesses
+        let empty = packed::Transaction::default();
+        assert_eq!(transaction_size(&empty), 68);
+        assert_eq!(transaction_weight(&empty), 72);
+        // a witness takes its offset, its length and its content
+        let witness = Bytes::from(vec![0u8; 85]).pack();
+        let tx = empty.as_builder().witnesses(vec![witness].pack()).build();
+        assert_eq!(transaction_size(&tx), 68 + 4 + 4 + 85);
+        assert_eq!(transaction_weight(&tx), 72 + 4 + 4 + 85);
+    }
+
+    #[test]
+    fn script_matches_code_hash_hash_type_and_args() {
+        let lock = secp256k1_lock();
+        let code_hash = lock.code_hash();
+        let args = lock.args().raw_data();
+        let hash_type = core::ScriptHashType::Type;
+        assert!(script_matches(&lock, &code_hash, hash_type, None));
+        assert!(script_matches(&lock, &code_hash, hash_type, Some(&args)));
+        assert!(script_matches(
+            &lock,
+            &code_hash,
+            hash_type,
+            Some(&args[..4])
+        ));
+        assert!(script_matches(&lock, &code_hash, hash_type, Some(&[])));
+        // the args are longer, or different
+        let mut longer = args.to_vec();
+        longer.push(0);
+        assert!(!script_matches(&lock, &code_hash, hash_type, Some(&longer)));
+        assert!(!script_matches(&lock, &code_hash, hash_type, Some(&[1])));
+        // the code hash or the hash type is different
+        let data = core::ScriptHashType::Data;
+        assert!(!script_matches(&lock, &code_hash, data, None));
+        assert!(!script_matches(&lock, &ZERO_H256.pack(), hash_type, None));
+    }
+
+    // A block which is built locally, there is no network to fetch a real one.
+    fn sample_block() -> core::BlockView {
+        let cellbase = core::TransactionBuilder::default()
+            .input(packed::CellInput::new_cellbase_input(1))
+            .witness(Default::default())
+            .build();
+        let transfers = (0..2u32).map(|index| {
+            let out_point = packed::OutPoint::new(cellbase.hash(), index);
+            core::TransactionBuilder::default()
+                .input(packed::CellInput::new(out_point, 0))
+                .output(Default::default())
+                .output_data(Default::default())
+                .witness(Bytes::from(vec![index as u8; 85]).pack())
+                .build()
+        });
+        core::BlockBuilder::default()
+            .number(1.pack())
+            .transaction(cellbase.clone())
+            .transactions(transfers)
+            .proposal([1u8; 10].pack())
+            .build()
+    }
+
+    // Changes the header without changing the body.
+    fn with_header(block: &core::BlockView, header: packed::Header) -> core::BlockView {
+        block
+            .data()
+            .as_builder()
+            .header(header)
+            .build()
+            .into_view_without_reset_header()
+    }
+
+    #[test]
+    fn verify_block_checks_the_roots() {
+        let block = sample_block();
+        verify_block(&block).unwrap();
+        let raw = block.data().header().raw();
+        let tampered = [
+            raw.clone()
+                .as_builder()
+                .transactions_root(ZERO_H256.pack())
+                .build(),
+            raw.clone()
+                .as_builder()
+                .proposals_hash(ZERO_H256.pack())
+                .build(),
+            raw.as_builder().uncles_hash([1u8; 32].pack()).build(),
+        ];
+        for raw in tampered.iter() {
+            let header = block.data().header().as_builder().raw(raw.clone()).build();
+            let result = verify_block(&with_header(&block, header));
+            assert!(matches!(result, Err(Error::Corruption(_))));
+        }
+    }
+
+    fn header_chain(count: u64) -> Vec<core::HeaderView> {
+        let mut headers: Vec<core::HeaderView> = Vec::new();
+        for number in 0..count {
+            let parent_hash = headers
+                .last()
+                .map(|parent| parent.hash())
+                .unwrap_or_default();
+            let header = core::HeaderBuilder::default()
+                .number(number.pack())
+                .parent_hash(parent_hash)
+                .build();
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn verify_header_chain_checks_the_links() {
+        let headers = header_chain(3);
+        verify_header_chain(&headers).unwrap();
+        verify_header_chain(&headers[..1]).unwrap();
+        verify_header_chain(&[]).unwrap();
+        let mut tampered = headers.clone();
+        tampered[2] = tampered[2]
+            .as_advanced_builder()
+            .parent_hash([1u8; 32].pack())
+            .build();
+        let result = verify_header_chain(&tampered);
+        assert!(matches!(result, Err(Error::Corruption(_))));
+        let mut skipped = headers.clone();
+        skipped[2] = skipped[2].as_advanced_builder().number(3.pack()).build();
+        let result = verify_header_chain(&skipped);
+        assert!(matches!(result, Err(Error::Corruption(_))));
+        let result = verify_header_chain(&[headers[0].clone(), headers[2].clone()]);
+        assert!(matches!(result, Err(Error::Corruption(_))));
+    }
+
+    // The same as `get_transaction_proof` of the node.
+    fn transaction_proof(block: &core::BlockView, indexes: &[u32]) -> rpc::TransactionProof {
+        let proof = CBMT::build_merkle_proof(block.tx_hashes(), indexes).unwrap();
+        rpc::TransactionProof {
+            block_hash: block.hash().unpack(),
+            witnesses_root: block.calc_witnesses_root().unpack(),
+            proof: rpc::MerkleProof {
+                indices: proof
+                    .indices()
+                    .iter()
+                    .map(|index| (*index).into())
+                    .collect(),
+                lemmas: proof.lemmas().iter().map(|lemma| lemma.unpack()).collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn verify_transaction_proof_against_the_header() {
+        let block = sample_block();
+        let header = block.header();
+        let tx_hashes: Vec<fixed::H256> = vec![block.tx_hashes()[1].unpack()];
+        let proof = transaction_proof(&block, &[1]);
+        assert!(verify_transaction_proof(&proof, &tx_hashes, &header));
+        // another transaction
+        let other: fixed::H256 = block.tx_hashes()[2].unpack();
+        assert!(!verify_transaction_proof(&proof, &[other], &header));
+        // a tampered proof
+        let mut tampered = proof.clone();
+        tampered.proof.lemmas[0] = ZERO_H256;
+        assert!(!verify_transaction_proof(&tampered, &tx_hashes, &header));
+        let mut tampered = proof.clone();
+        tampered.witnesses_root = ZERO_H256;
+        assert!(!verify_transaction_proof(&tampered, &tx_hashes, &header));
+        // the header of another block
+        let other_header = header.as_advanced_builder().number(2.pack()).build();
+        assert!(!verify_transaction_proof(&proof, &tx_hashes, &other_header));
+    }
+
+    #[test]
+    fn alert_signatures_recover_to_the_signers() {
+        let raw = build_alert(1, 0, None, Some("0.38.0"), 1, 1_600_000_000_000, "upgrade");
+        let privkeys = [
+            Privkey::from(h256!("0x1")),
+            Privkey::from(h256!(
+                "0xd00c06bfd800d27397002dca6fb0993d5ba6399b4238b2f29ee9deb97593d2bc"
+            )),
+        ];
+        let alert = sign_alert(raw.clone(), &privkeys).unwrap();
+        assert_eq!(alert.raw().as_slice(), raw.as_slice());
+        // the node verifies the signatures over the blake2b hash of the raw alert
+        let hash = blake2b_256_with_personal(raw.as_slice(), DEFAULT_PERSONAL).unwrap();
+        let message = Message::from_slice(&hash).unwrap();
+        let signatures = alert.signatures();
+        assert_eq!(signatures.len(), privkeys.len());
+        for (signature, privkey) in signatures.into_iter().zip(privkeys.iter()) {
+            let signature = Signature::from_slice(&signature.raw_data()).unwrap();
+            assert_eq!(
+                signature.recover(&message).unwrap(),
+                privkey.pubkey().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn dao_is_found_by_the_data_hash() {
+        let genesis = genesis(&[SIGHASH, MULTISIG, SECP256K1_DATA, DAO], &[]);
+        let (code_hash, cell_dep) = dao_code_hash_and_dep_with(&genesis, &data_hash(DAO)).unwrap();
+        let input = packed::CellInput::new_cellbase_input(0);
+        let expected: fixed::H256 = type_id_script(calculate_type_id(&input, 4))
+            .calc_script_hash()
+            .unpack();
+        assert_eq!(code_hash, expected);
+        let out_point = packed::OutPoint::new(genesis.transactions()[0].hash(), 4);
+        assert_eq!(cell_dep.out_point(), out_point);
+        assert_eq!(cell_dep.dep_type(), core::DepType::Code.into());
+    }
+
+    // The binary of the fixture is not the real one, so the type hash is used.
+    #[test]
+    fn dao_falls_back_to_the_standard_type_hash() {
+        let standard = genesis(&[SIGHASH, DAO, SECP256K1_DATA, MULTISIG], &[]);
+        let (code_hash, cell_dep) = dao_code_hash_and_dep(&standard).unwrap();
+        assert_eq!(code_hash, DAO_TYPE_HASH);
+        assert_eq!(
+            code_hash,
+            h256!("0x82d76d1b75fe2fd9a27dfbaa65a039221a380d76c926f378d3f81cf3e7e13f2e")
+        );
+        let out_point = packed::OutPoint::new(standard.transactions()[0].hash(), 2);
+        assert_eq!(cell_dep.out_point(), out_point);
+        let without_dao = genesis(&[SIGHASH], &[]);
+        let result = dao_code_hash_and_dep(&without_dao);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+}
@@ -43,6 +43,18 @@ pub(crate) mod http {
             block_number: BlockNumber,
             verbosity: Option<Uint32>,
         ) -> Result<Option<BlockView>>;
+        #[rpc(name = "get_block")]
+        fn get_packed_block(
+            &self,
+            block_hash: H256,
+            verbosity: Option<Uint32>,
+        ) -> Result<Option<JsonBytes>>;
+        #[rpc(name = "get_block_by_number")]
+        fn get_packed_block_by_number(
+            &self,
+            block_number: BlockNumber,
+            verbosity: Option<Uint32>,
+        ) -> Result<Option<JsonBytes>>;
         #[rpc(name = "get_header")]
         fn get_header(
             &self,
@@ -143,6 +155,8 @@ pub(crate) mod http {
         // Module Experiment
         #[rpc(name = "dry_run_transaction")]
         fn dry_run_transaction(&self, tx: Transaction) -> Result<DryRunResult>;
+        #[rpc(name = "estimate_cycles")]
+        fn estimate_cycles(&self, tx: Transaction) -> Result<DryRunResult>;
         #[rpc(name = "calculate_dao_maximum_withdraw")]
         fn calculate_dao_maximum_withdraw(
             &self,
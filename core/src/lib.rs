@@ -18,6 +18,27 @@ pub mod types {
             NewTipBlock,
             NewTransaction,
         }
+        impl ::std::fmt::Display for Topic {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                let name = match self {
+                    Self::NewTipHeader => "new_tip_header",
+                    Self::NewTipBlock => "new_tip_block",
+                    Self::NewTransaction => "new_transaction",
+                };
+                write!(f, "{}", name)
+            }
+        }
+        impl ::std::str::FromStr for Topic {
+            type Err = String;
+            fn from_str(name: &str) -> ::std::result::Result<Self, Self::Err> {
+                match name {
+                    "new_tip_header" => Ok(Self::NewTipHeader),
+                    "new_tip_block" => Ok(Self::NewTipBlock),
+                    "new_transaction" => Ok(Self::NewTransaction),
+                    _ => Err(format!("unknown topic {:?}", name)),
+                }
+            }
+        }
     }
     pub use ckb_types::{bytes, constants, error, packed, prelude, utilities};
     pub mod core {
@@ -30,3 +51,32 @@ pub mod types {
 }
 
 pub mod client;
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::serde_json;
+
+    use super::types::rpc::Topic;
+
+    #[test]
+    fn topic_round_trip() {
+        for topic in &[
+            Topic::NewTipHeader,
+            Topic::NewTipBlock,
+            Topic::NewTransaction,
+        ] {
+            let name = topic.to_string();
+            assert_eq!(name.parse::<Topic>().unwrap(), *topic);
+            // the names are the same as the ones in the subscribe requests
+            let json = serde_json::to_string(topic).unwrap();
+            assert_eq!(json, format!("{:?}", name));
+        }
+    }
+
+    #[test]
+    fn unknown_topic_is_rejected() {
+        let err = "new_tip".parse::<Topic>().unwrap_err();
+        assert_eq!(err, "unknown topic \"new_tip\"");
+        assert!("NewTipHeader".parse::<Topic>().is_err());
+    }
+}